@@ -0,0 +1,47 @@
+//! Print every highlight from a relay to stderr.
+//!
+//! ```text
+//! WEECHAT_PASSWORD=... cargo run --example highlights -- localhost:9000
+//! ```
+
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::env;
+use std::process;
+use weechat_client::{BufferStore, RelayConfig, WeechatRelay};
+use weechat_parser::events::RelayEvent;
+
+fn main() {
+    let address = env::args().nth(1).unwrap_or("localhost:9000".to_owned());
+    let password = env::var("WEECHAT_PASSWORD").unwrap_or_default();
+    let mut relay = match WeechatRelay::connect_with(&RelayConfig::tcp(&address, &password)) {
+        Ok(relay) => relay,
+        Err(e) => {
+            eprintln!("couldn't connect to {}: {}", address, e);
+            process::exit(1);
+        }
+    };
+    let mut buffers = BufferStore::fetch(&mut relay).unwrap();
+    relay.sync(&[]).unwrap();
+
+    for event in relay.events() {
+        match event {
+            Ok(RelayEvent::LineAdded(lines)) => {
+                for line in lines {
+                    if line.highlight {
+                        let buffer = buffers.by_pointer(&line.buffer)
+                                            .map(|buffer| &buffer.short_name[..])
+                                            .unwrap_or("?");
+                        eprintln!("Got message in {}: {} {}", buffer, line.prefix, line.message);
+                    }
+                }
+            }
+            Ok(event) => {
+                buffers.apply(&event);
+            }
+            Err(e) => eprintln!("error decoding {}", e),
+        }
+    }
+    eprintln!("done reading");
+}
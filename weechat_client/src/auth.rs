@@ -0,0 +1,475 @@
+//! Authentication with the relay: the `handshake` command, password hashing and the `init` line.
+//!
+//! See the "handshake" and "init" sections of the relay protocol documentation for the details of
+//! what is sent here.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use weechat_parser::{WeechatData, WeechatMessage};
+use weechat_parser::errors::WeechatParseError;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum HashAlgorithm {
+    Plain,
+    Sha256,
+    Sha512,
+    Pbkdf2Sha256,
+    Pbkdf2Sha512,
+}
+
+/// Every algorithm the client knows, offered to the relay in this order.
+pub const ALL_ALGORITHMS: [HashAlgorithm; 5] = [HashAlgorithm::Plain,
+                                                HashAlgorithm::Sha256,
+                                                HashAlgorithm::Sha512,
+                                                HashAlgorithm::Pbkdf2Sha256,
+                                                HashAlgorithm::Pbkdf2Sha512];
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HashAlgorithm::Plain => "plain",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Pbkdf2Sha256 => "pbkdf2+sha256",
+            HashAlgorithm::Pbkdf2Sha512 => "pbkdf2+sha512",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        ALL_ALGORITHMS.iter().cloned().find(|algorithm| algorithm.name() == name)
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Io(io::Error),
+    Parse(WeechatParseError),
+    /// The handshake reply was missing something we need to build the `init` line.
+    InvalidHandshake(String),
+    /// The relay picked a hash algorithm we don't know, or none at all.
+    UnsupportedAlgorithm(String),
+    /// The relay closed the connection after `init`, the password (or TOTP) was wrong.
+    Rejected,
+    /// The relay wants a TOTP code and none was given.
+    TotpRequired,
+    /// The TOTP secret isn't valid base32.
+    InvalidTotpSecret,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            AuthError::Io(ref e) => write!(f, "io error during authentication: {}", e),
+            AuthError::Parse(ref e) => write!(f, "couldn't parse relay reply: {}", e),
+            AuthError::InvalidHandshake(ref detail) => write!(f, "invalid handshake: {}", detail),
+            AuthError::UnsupportedAlgorithm(ref name) => {
+                write!(f, "unsupported password hash algorithm: {:?}", name)
+            }
+            AuthError::Rejected => f.write_str("relay rejected the credentials"),
+            AuthError::TotpRequired => f.write_str("relay requires a TOTP code"),
+            AuthError::InvalidTotpSecret => f.write_str("TOTP secret is not valid base32"),
+        }
+    }
+}
+
+impl error::Error for AuthError {
+    fn description(&self) -> &str {
+        match *self {
+            AuthError::Io(_) => "io error during authentication",
+            AuthError::Parse(_) => "couldn't parse relay reply",
+            AuthError::InvalidHandshake(_) => "invalid handshake",
+            AuthError::UnsupportedAlgorithm(_) => "unsupported password hash algorithm",
+            AuthError::Rejected => "relay rejected the credentials",
+            AuthError::TotpRequired => "relay requires a TOTP code",
+            AuthError::InvalidTotpSecret => "TOTP secret is not valid base32",
+        }
+    }
+}
+
+impl From<io::Error> for AuthError {
+    fn from(error: io::Error) -> AuthError {
+        AuthError::Io(error)
+    }
+}
+
+impl From<WeechatParseError> for AuthError {
+    fn from(error: WeechatParseError) -> AuthError {
+        AuthError::Parse(error)
+    }
+}
+
+/// What the relay told us in its reply to `handshake`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Handshake {
+    pub algorithm: HashAlgorithm,
+    pub iterations: u32,
+    pub nonce: Vec<u8>,
+    pub totp: bool,
+}
+
+impl Handshake {
+    pub fn from_message(message: &WeechatMessage) -> Result<Handshake, AuthError> {
+        let entries = match message.data.get(0) {
+            Some(&WeechatData::Hashtable(_, _, ref entries)) => entries,
+            other => {
+                return Err(AuthError::InvalidHandshake(format!("expected a hashtable, found {:?}",
+                                                               other)))
+            }
+        };
+        let get = |key: &str| {
+            entries.iter()
+                   .find(|&&(ref k, _)| k == &WeechatData::String(key.to_owned()))
+                   .and_then(|&(_, ref v)| match *v {
+                       WeechatData::String(ref value) => Some(value.clone()),
+                       _ => None,
+                   })
+        };
+
+        let algorithm_name = get("password_hash_algo").unwrap_or_default();
+        let algorithm = try!(HashAlgorithm::from_name(&algorithm_name)
+                                 .ok_or(AuthError::UnsupportedAlgorithm(algorithm_name)));
+        let iterations = match get("password_hash_iterations") {
+            Some(value) => {
+                try!(value.parse()
+                          .map_err(|_| AuthError::InvalidHandshake(format!("bad iterations {:?}",
+                                                                           value))))
+            }
+            None => 0,
+        };
+        let nonce = match get("nonce") {
+            Some(value) => {
+                try!(from_hex(&value)
+                         .ok_or(AuthError::InvalidHandshake(format!("bad nonce {:?}", value))))
+            }
+            None => vec![],
+        };
+        if algorithm != HashAlgorithm::Plain && nonce.is_empty() {
+            return Err(AuthError::InvalidHandshake("missing nonce".to_owned()));
+        }
+        let pbkdf2 = match algorithm {
+            HashAlgorithm::Pbkdf2Sha256 | HashAlgorithm::Pbkdf2Sha512 => true,
+            _ => false,
+        };
+        if pbkdf2 && iterations == 0 {
+            return Err(AuthError::InvalidHandshake("missing iterations".to_owned()));
+        }
+        Ok(Handshake {
+            algorithm: algorithm,
+            iterations: iterations,
+            nonce: nonce,
+            totp: get("totp").map(|value| value == "on").unwrap_or(false),
+        })
+    }
+}
+
+/// The `handshake` command offering `algorithms`.
+pub fn handshake_command(algorithms: &[HashAlgorithm]) -> String {
+    let names = algorithms.iter().map(|algorithm| algorithm.name()).collect::<Vec<_>>();
+    format!("(handshake) handshake password_hash_algo={},compression=zstd:zlib:off\n",
+            names.join(":"))
+}
+
+/// The `init` command for `password`, hashed as the relay asked in `handshake`. Relays too old to
+/// answer the handshake only understand plain passwords, so `None` sends it in plain text.
+pub fn init_command(handshake: Option<&Handshake>, password: &str, totp: Option<&str>) -> String {
+    let mut client_nonce = [0; 16];
+    rand::thread_rng().fill_bytes(&mut client_nonce);
+    init_command_with_nonce(handshake, password, totp, &client_nonce)
+}
+
+pub fn init_command_with_nonce(handshake: Option<&Handshake>,
+                               password: &str,
+                               totp: Option<&str>,
+                               client_nonce: &[u8])
+                               -> String {
+    let mut command = match handshake {
+        Some(handshake) if handshake.algorithm != HashAlgorithm::Plain => {
+            let mut salt = handshake.nonce.clone();
+            salt.extend_from_slice(client_nonce);
+            let hash = hash_password(handshake.algorithm, &salt, handshake.iterations, password);
+            match handshake.algorithm {
+                HashAlgorithm::Pbkdf2Sha256 |
+                HashAlgorithm::Pbkdf2Sha512 => {
+                    format!("init password_hash={}:{}:{}:{}",
+                            handshake.algorithm.name(),
+                            to_hex(&salt),
+                            handshake.iterations,
+                            to_hex(&hash))
+                }
+                _ => {
+                    format!("init password_hash={}:{}:{}",
+                            handshake.algorithm.name(),
+                            to_hex(&salt),
+                            to_hex(&hash))
+                }
+            }
+        }
+        _ => format!("init password={}", escape_option(password)),
+    };
+    if let Some(code) = totp {
+        command.push_str(&format!(",totp={}", escape_option(code)));
+    }
+    command.push('\n');
+    command
+}
+
+/// Hash `password` with `salt` the way the relay expects for `algorithm`.
+pub fn hash_password(algorithm: HashAlgorithm,
+                     salt: &[u8],
+                     iterations: u32,
+                     password: &str)
+                     -> Vec<u8> {
+    match algorithm {
+        HashAlgorithm::Plain => password.as_bytes().to_vec(),
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            hasher.update(salt);
+            hasher.update(password.as_bytes());
+            hasher.finalize().to_vec()
+        }
+        HashAlgorithm::Sha512 => {
+            let mut hasher = Sha512::new();
+            hasher.update(salt);
+            hasher.update(password.as_bytes());
+            hasher.finalize().to_vec()
+        }
+        HashAlgorithm::Pbkdf2Sha256 => {
+            let mut output = [0; 32];
+            pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut output);
+            output.to_vec()
+        }
+        HashAlgorithm::Pbkdf2Sha512 => {
+            let mut output = [0; 64];
+            pbkdf2_hmac::<Sha512>(password.as_bytes(), salt, iterations, &mut output);
+            output.to_vec()
+        }
+    }
+}
+
+/// Seconds each TOTP code is valid for, WeeChat uses the RFC 6238 default.
+pub const TOTP_PERIOD: u64 = 30;
+/// Digits in a TOTP code, like `/secure totp` and most authenticator apps.
+pub const TOTP_DIGITS: u32 = 6;
+
+/// The second factor for relays with `relay.network.totp_secret` set.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Totp {
+    /// A code read off an authenticator, only good for the current window.
+    Code(String),
+    /// The shared secret, codes are computed at connect time.
+    Secret(Vec<u8>),
+}
+
+impl Totp {
+    /// The secret as WeeChat and authenticator apps show it, in base32.
+    pub fn from_base32(secret: &str) -> Result<Totp, AuthError> {
+        base32_decode(secret).map(Totp::Secret).ok_or(AuthError::InvalidTotpSecret)
+    }
+
+    /// Codes to try at `unix_time`, the current window first and then the windows either side
+    /// of it in case our clock and the relay's disagree.
+    pub fn codes_at(&self, unix_time: u64) -> Vec<String> {
+        match *self {
+            Totp::Code(ref code) => vec![code.clone()],
+            Totp::Secret(ref secret) => {
+                let mut times = vec![unix_time, unix_time.saturating_sub(TOTP_PERIOD)];
+                times.push(unix_time + TOTP_PERIOD);
+                times.iter().map(|&time| totp_code(secret, time, TOTP_DIGITS)).collect()
+            }
+        }
+    }
+
+    pub fn codes(&self) -> Vec<String> {
+        self.codes_at(unix_time())
+    }
+}
+
+/// Seconds since the epoch by the system clock.
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// The RFC 6238 code for `secret` at `unix_time`, with HMAC-SHA1 and a 30 second period.
+pub fn totp_code(secret: &[u8], unix_time: u64, digits: u32) -> String {
+    let counter = unix_time / TOTP_PERIOD;
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation from RFC 4226.
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = ((hash[offset] as u32 & 0x7f) << 24) | ((hash[offset + 1] as u32) << 16) |
+                ((hash[offset + 2] as u32) << 8) | hash[offset + 3] as u32;
+    format!("{:0width$}", value % 10u32.pow(digits), width = digits as usize)
+}
+
+/// Decode RFC 4648 base32, ignoring case, padding and the spaces apps group secrets with.
+pub fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut output = vec![];
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in value.chars().filter(|&c| c != '=' && !c.is_whitespace()) {
+        let digit = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            _ => return None,
+        };
+        bits = (bits << 5) | digit;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            output.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    if output.is_empty() {
+        None
+    } else {
+        Some(output)
+    }
+}
+
+/// Options in `init` are separated by commas, so commas in values are escaped with a backslash.
+pub fn escape_option(value: &str) -> String {
+    value.replace(',', "\\,")
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 || !value.is_ascii() {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&value[index..index + 2], 16).ok())
+        .collect()
+}
+
+#[test]
+fn test_hash_password() {
+    let salt = from_hex("a1b2c3d400112233").unwrap();
+    assert_eq!(to_hex(&hash_password(HashAlgorithm::Sha256, &salt, 0, "secret")),
+               "9bd3b5a2e746002fbf56eb2d7ea51f2f619055d58cf8fac499cd0d627a4ab81c");
+    assert_eq!(to_hex(&hash_password(HashAlgorithm::Sha512, &salt, 0, "secret")),
+               "6aeac867bcbe08eaa710297fc5b88fbe6473c42b22ac115816c374355c5a2421\
+                95a28609ba59803e01364122ec49273eca8569c76c45634cc389dae4383941ce");
+    assert_eq!(to_hex(&hash_password(HashAlgorithm::Pbkdf2Sha256, &salt, 100000, "secret")),
+               "fc4b7a00a658ef99f70233ae3b73c3fa77c20769e5ca222b87194c0311d4c873");
+    assert_eq!(to_hex(&hash_password(HashAlgorithm::Pbkdf2Sha512, &salt, 1000, "secret")),
+               "b77e4b93ca7239239d8fe9c9be8d22d3cc6637995bc96f0f51194c540d6883c0\
+                0e2bb9fbca393aaa9b21b18f42ee16cbb2a0b50dd481d856944a7d93faea93c9");
+}
+
+#[test]
+fn test_init_command() {
+    assert_eq!(init_command_with_nonce(None, "pass,word", None, &[]),
+               "init password=pass\\,word\n");
+    let handshake = Handshake {
+        algorithm: HashAlgorithm::Sha256,
+        iterations: 0,
+        nonce: vec![0xa1, 0xb2, 0xc3, 0xd4],
+        totp: false,
+    };
+    assert_eq!(init_command_with_nonce(Some(&handshake), "secret", None, &[0x00, 0x11, 0x22, 0x33]),
+               "init password_hash=sha256:a1b2c3d400112233:\
+                9bd3b5a2e746002fbf56eb2d7ea51f2f619055d58cf8fac499cd0d627a4ab81c\n");
+    let handshake = Handshake {
+        algorithm: HashAlgorithm::Pbkdf2Sha256,
+        iterations: 100000,
+        ..handshake
+    };
+    assert_eq!(init_command_with_nonce(Some(&handshake), "secret", None, &[0x00, 0x11, 0x22, 0x33]),
+               "init password_hash=pbkdf2+sha256:a1b2c3d400112233:100000:\
+                fc4b7a00a658ef99f70233ae3b73c3fa77c20769e5ca222b87194c0311d4c873\n");
+}
+
+#[test]
+fn test_handshake_from_message() {
+    let entry = |key: &str, value: &str| {
+        (WeechatData::String(key.to_owned()), WeechatData::String(value.to_owned()))
+    };
+    let message = WeechatMessage {
+        id: "handshake".to_owned(),
+        data: vec![WeechatData::Hashtable("str".to_owned(),
+                                          "str".to_owned(),
+                                          vec![entry("password_hash_algo", "pbkdf2+sha512"),
+                                               entry("password_hash_iterations", "100000"),
+                                               entry("totp", "on"),
+                                               entry("nonce", "85B1EE00695A5B254E14F4885538DF0D"),
+                                               entry("compression", "off")])],
+    };
+    assert_eq!(Handshake::from_message(&message).unwrap(),
+               Handshake {
+                   algorithm: HashAlgorithm::Pbkdf2Sha512,
+                   iterations: 100000,
+                   nonce: from_hex("85b1ee00695a5b254e14f4885538df0d").unwrap(),
+                   totp: true,
+               });
+
+    let message = WeechatMessage {
+        id: "handshake".to_owned(),
+        data: vec![WeechatData::Hashtable("str".to_owned(),
+                                          "str".to_owned(),
+                                          vec![entry("password_hash_algo", "md5")])],
+    };
+    match Handshake::from_message(&message) {
+        Err(AuthError::UnsupportedAlgorithm(name)) => assert_eq!(name, "md5"),
+        other => panic!("expected UnsupportedAlgorithm, got {:?}", other),
+    }
+
+    for iterations in &[None, Some("0")] {
+        let mut entries = vec![entry("password_hash_algo", "pbkdf2+sha256"),
+                               entry("nonce", "85B1EE00695A5B254E14F4885538DF0D")];
+        if let Some(iterations) = *iterations {
+            entries.push(entry("password_hash_iterations", iterations));
+        }
+        let message = WeechatMessage {
+            id: "handshake".to_owned(),
+            data: vec![WeechatData::Hashtable("str".to_owned(), "str".to_owned(), entries)],
+        };
+        match Handshake::from_message(&message) {
+            Err(AuthError::InvalidHandshake(_)) => {}
+            other => panic!("expected InvalidHandshake, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_init_command_with_totp() {
+    assert_eq!(init_command_with_nonce(None, "secret", Some("123456"), &[]),
+               "init password=secret,totp=123456\n");
+}
+
+#[test]
+fn test_totp_rfc6238_vectors() {
+    // Appendix B of RFC 6238, SHA1 with the 20 byte ASCII secret and 8 digits.
+    let secret = b"12345678901234567890";
+    assert_eq!(totp_code(secret, 59, 8), "94287082");
+    assert_eq!(totp_code(secret, 1111111109, 8), "07081804");
+    assert_eq!(totp_code(secret, 1111111111, 8), "14050471");
+    assert_eq!(totp_code(secret, 1234567890, 8), "89005924");
+    assert_eq!(totp_code(secret, 2000000000, 8), "69279037");
+    assert_eq!(totp_code(secret, 20000000000, 8), "65353130");
+    assert_eq!(totp_code(secret, 59, 6), "287082");
+}
+
+#[test]
+fn test_totp_from_base32() {
+    let totp = Totp::from_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+    assert_eq!(totp, Totp::Secret(b"12345678901234567890".to_vec()));
+    assert_eq!(totp.codes_at(1111111111), vec!["050471", "081804", "266759"]);
+    assert_eq!(Totp::Code("123456".to_owned()).codes_at(1111111111), vec!["123456"]);
+    match Totp::from_base32("not base32!") {
+        Err(AuthError::InvalidTotpSecret) => {}
+        other => panic!("expected InvalidTotpSecret, got {:?}", other),
+    }
+}
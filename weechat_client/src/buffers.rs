@@ -0,0 +1,218 @@
+//! The buffers open in WeeChat, kept current from the `_buffer_*` events.
+//!
+//! Lines and nicklist changes only name their buffer by pointer, a `BufferStore` turns that
+//! pointer back into a buffer with a name. Load it with `BufferStore::fetch` and `apply` every
+//! event after that; events only arrive for buffers synced with the `buffers` option.
+
+use std::collections::HashMap;
+use weechat_parser::errors::WeechatParseError;
+use weechat_parser::events::{BufferChange, RelayEvent};
+use weechat_parser::WeechatMessage;
+use {ClientError, WeechatRelay};
+
+/// The `hdata` that loads every buffer with the fields `Buffer` has.
+pub const BUFFERS_HDATA: &'static str = "buffer:gui_buffers(*) \
+                                         number,full_name,short_name,type,nicklist,title,\
+                                         local_variables";
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Buffer {
+    pub pointer: String,
+    /// Merged buffers share their number.
+    pub number: i32,
+    pub full_name: String,
+    pub short_name: String,
+    pub title: String,
+    /// 0 for buffers with formatted lines, 1 for free content buffers.
+    pub buffer_type: i32,
+    pub nicklist: bool,
+    pub local_variables: HashMap<String, String>,
+}
+
+impl Buffer {
+    fn from_change(change: &BufferChange) -> Buffer {
+        let mut buffer = Buffer {
+            pointer: change.pointer.clone(),
+            number: change.number,
+            full_name: change.full_name.clone(),
+            short_name: String::new(),
+            title: String::new(),
+            buffer_type: 0,
+            nicklist: false,
+            local_variables: HashMap::new(),
+        };
+        buffer.update(change);
+        buffer
+    }
+
+    /// Take every field `change` carries.
+    fn update(&mut self, change: &BufferChange) {
+        self.number = change.number;
+        self.full_name = change.full_name.clone();
+        if let Some(ref short_name) = change.short_name {
+            self.short_name = short_name.clone();
+        }
+        if let Some(ref title) = change.title {
+            self.title = title.clone();
+        }
+        if let Some(buffer_type) = change.buffer_type {
+            self.buffer_type = buffer_type;
+        }
+        if let Some(nicklist) = change.nicklist {
+            self.nicklist = nicklist;
+        }
+        if let Some(ref local_variables) = change.local_variables {
+            self.local_variables = local_variables.clone();
+        }
+    }
+}
+
+/// Buffers by pointer.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct BufferStore {
+    buffers: HashMap<String, Buffer>,
+}
+
+impl BufferStore {
+    pub fn new() -> BufferStore {
+        BufferStore::default()
+    }
+
+    /// Ask `relay` for every buffer and load them.
+    pub fn fetch(relay: &mut WeechatRelay) -> Result<BufferStore, ClientError> {
+        let request = try!(relay.hdata(BUFFERS_HDATA));
+        let reply = try!(relay.wait(request));
+        let mut store = BufferStore::new();
+        try!(store.load(&reply));
+        Ok(store)
+    }
+
+    /// Replace every buffer with the ones in `reply`, the reply to `BUFFERS_HDATA`.
+    pub fn load(&mut self, reply: &WeechatMessage) -> Result<(), WeechatParseError> {
+        let changes = try!(BufferChange::all_from(reply));
+        self.buffers = changes.iter()
+                              .map(|change| (change.pointer.clone(), Buffer::from_change(change)))
+                              .collect();
+        Ok(())
+    }
+
+    /// Keep up with `event`, returning whether it was about buffers.
+    pub fn apply(&mut self, event: &RelayEvent) -> bool {
+        match *event {
+            RelayEvent::BufferClosing(ref changes) => {
+                for change in changes {
+                    self.buffers.remove(&change.pointer);
+                }
+            }
+            RelayEvent::BufferOpened(ref changes) |
+            RelayEvent::BufferTypeChanged(ref changes) |
+            RelayEvent::BufferMoved(ref changes) |
+            RelayEvent::BufferMerged(ref changes) |
+            RelayEvent::BufferUnmerged(ref changes) |
+            RelayEvent::BufferHidden(ref changes) |
+            RelayEvent::BufferUnhidden(ref changes) |
+            RelayEvent::BufferRenamed(ref changes) |
+            RelayEvent::BufferTitleChanged(ref changes) |
+            RelayEvent::BufferLocalvarAdded(ref changes) |
+            RelayEvent::BufferLocalvarChanged(ref changes) |
+            RelayEvent::BufferLocalvarRemoved(ref changes) |
+            RelayEvent::BufferCleared(ref changes) => {
+                for change in changes {
+                    // Buffers we missed the opening of are picked up as they change.
+                    if let Some(buffer) = self.buffers.get_mut(&change.pointer) {
+                        buffer.update(change);
+                        continue;
+                    }
+                    self.buffers.insert(change.pointer.clone(), Buffer::from_change(change));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn by_pointer(&self, pointer: &str) -> Option<&Buffer> {
+        self.buffers.get(pointer)
+    }
+
+    pub fn by_full_name(&self, full_name: &str) -> Option<&Buffer> {
+        self.buffers.values().find(|buffer| buffer.full_name == full_name)
+    }
+
+    /// Every buffer with `number`, more than one if they are merged.
+    pub fn by_number(&self, number: i32) -> Vec<&Buffer> {
+        let mut buffers: Vec<&Buffer> = self.buffers
+                                            .values()
+                                            .filter(|buffer| buffer.number == number)
+                                            .collect();
+        buffers.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+        buffers
+    }
+
+    /// Every buffer, ordered by number.
+    pub fn buffers(&self) -> Vec<&Buffer> {
+        let mut buffers: Vec<&Buffer> = self.buffers.values().collect();
+        buffers.sort_by(|a, b| (a.number, &a.full_name).cmp(&(b.number, &b.full_name)));
+        buffers
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+}
+
+#[test]
+fn test_buffer_store() {
+    use weechat_parser::WeechatData;
+
+    let buffer = |pointer: &str, number: i32, full_name: &str| {
+        BufferChange {
+            pointer: pointer.to_owned(),
+            number: number,
+            full_name: full_name.to_owned(),
+            short_name: None,
+            title: None,
+            nicklist: None,
+            buffer_type: None,
+            local_variables: None,
+            prev_buffer: None,
+            next_buffer: None,
+        }
+    };
+    let reply = WeechatMessage {
+        id: "r1".to_owned(),
+        data: vec![WeechatData::Hdata("buffer".to_owned(),
+                                      vec![WeechatData::Pointer("0x1".to_owned())],
+                                      vec![vec![("number".to_owned(), WeechatData::Int(1)),
+                                                ("full_name".to_owned(),
+                                                 WeechatData::String("core.weechat".to_owned())),
+                                                ("title".to_owned(),
+                                                 WeechatData::String("WeeChat".to_owned()))]
+                                               .into_iter()
+                                               .collect()])],
+    };
+    let mut store = BufferStore::new();
+    store.load(&reply).unwrap();
+    assert_eq!(store.by_pointer("0x1").unwrap().title, "WeeChat");
+
+    assert!(store.apply(&RelayEvent::BufferOpened(vec![buffer("0x7fcab15936d0",
+                                                              2,
+                                                              "irc.libera.#weechat")])));
+    let mut renamed = buffer("0x7fcab15936d0", 2, "irc.libera.#weechat-dev");
+    renamed.short_name = Some("#weechat-dev".to_owned());
+    assert!(store.apply(&RelayEvent::BufferRenamed(vec![renamed])));
+    assert!(store.by_full_name("irc.libera.#weechat").is_none());
+    let channel = store.by_full_name("irc.libera.#weechat-dev").unwrap();
+    assert_eq!((&channel.pointer[..], &channel.short_name[..]),
+               ("0x7fcab15936d0", "#weechat-dev"));
+
+    assert!(store.apply(&RelayEvent::BufferMerged(vec![buffer("0x1", 2, "core.weechat")])));
+    assert_eq!(store.by_number(2).len(), 2);
+    assert!(store.apply(&RelayEvent::BufferClosing(vec![buffer("0x1", 2, "core.weechat")])));
+    assert_eq!(store.buffers(), vec![store.by_pointer("0x7fcab15936d0").unwrap()]);
+    assert!(!store.apply(&RelayEvent::Upgrade));
+}
@@ -0,0 +1,324 @@
+//! Recording and replaying raw relay sessions.
+//!
+//! A capture file starts with the `WCAP` magic, a version byte and the unix time in milliseconds
+//! the capture started at. It is followed by one record per chunk of bytes read or written:
+//!
+//! ```text
+//! direction (u8) | elapsed microseconds (u64) | length (u32) | bytes
+//! ```
+//!
+//! with every number big endian. Chunk boundaries are kept as they were seen on the wire so
+//! replays hit the same partial-message paths as the original session.
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use weechat_parser;
+use weechat_parser::WeechatMessage;
+use weechat_parser::errors::WeechatParseError;
+
+const MAGIC: &'static [u8] = b"WCAP";
+const VERSION: u8 = 1;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Direction {
+    /// Bytes read from the relay.
+    Received,
+    /// Bytes written to the relay.
+    Sent,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Chunk {
+    pub direction: Direction,
+    pub elapsed: Duration,
+    pub data: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Speed {
+    /// Keep the gaps between chunks as they were recorded.
+    Original,
+    /// Divide the gaps between chunks by the given factor.
+    Accelerated(u32),
+    /// Send every chunk as fast as possible.
+    Instant,
+}
+
+impl Speed {
+    fn scale(&self, elapsed: Duration) -> Duration {
+        match *self {
+            Speed::Original => elapsed,
+            Speed::Accelerated(factor) => elapsed / factor.max(1),
+            Speed::Instant => Duration::from_secs(0),
+        }
+    }
+}
+
+/// Writes chunks to a capture. Clones share the same output so the reading and writing halves
+/// of a connection can record into one file.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<RecorderInner>>,
+}
+
+struct RecorderInner {
+    output: Box<dyn Write + Send>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub fn new<W: Write + Send + 'static>(mut output: W) -> io::Result<Recorder> {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let millis = since_epoch.as_secs() * 1000 + since_epoch.subsec_millis() as u64;
+        try!(output.write_all(MAGIC));
+        try!(output.write_all(&[VERSION]));
+        try!(output.write_all(&millis.to_be_bytes()));
+        Ok(Recorder {
+            inner: Arc::new(Mutex::new(RecorderInner {
+                output: Box::new(output),
+                started: Instant::now(),
+            })),
+        })
+    }
+
+    /// Create a new capture named after the current time in `directory`, returning the recorder
+    /// and the path of the file.
+    pub fn create_in<P: AsRef<Path>>(directory: P) -> io::Result<(Recorder, PathBuf)> {
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let path = directory.as_ref().join(format!("weechat-{}-{:09}.wcap",
+                                                   since_epoch.as_secs(),
+                                                   since_epoch.subsec_nanos()));
+        let file = try!(File::create(&path));
+        let recorder = try!(Recorder::new(file));
+        Ok((recorder, path))
+    }
+
+    pub fn record(&self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let elapsed = inner.started.elapsed();
+        let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+        let flag = match direction {
+            Direction::Received => 0u8,
+            Direction::Sent => 1u8,
+        };
+        try!(inner.output.write_all(&[flag]));
+        try!(inner.output.write_all(&micros.to_be_bytes()));
+        try!(inner.output.write_all(&(data.len() as u32).to_be_bytes()));
+        try!(inner.output.write_all(data));
+        inner.output.flush()
+    }
+
+    /// Wrap `stream` so everything read from or written to it is also recorded.
+    pub fn tee<S>(&self, stream: S) -> RecordingStream<S> {
+        RecordingStream {
+            inner: stream,
+            recorder: self.clone(),
+        }
+    }
+}
+
+pub struct RecordingStream<S> {
+    inner: S,
+    recorder: Recorder,
+}
+
+impl<S> RecordingStream<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for RecordingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = try!(self.inner.read(buf));
+        if count > 0 {
+            try!(self.recorder.record(Direction::Received, &buf[..count]));
+        }
+        Ok(count)
+    }
+}
+
+impl<S: Write> Write for RecordingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = try!(self.inner.write(buf));
+        if count > 0 {
+            try!(self.recorder.record(Direction::Sent, &buf[..count]));
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Capture {
+    pub started: SystemTime,
+    pub chunks: Vec<Chunk>,
+}
+
+impl Capture {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Capture> {
+        let file = try!(File::open(path));
+        Capture::read_from(io::BufReader::new(file))
+    }
+
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<Capture> {
+        let mut header = [0; 13];
+        try!(input.read_exact(&mut header));
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a capture file"));
+        }
+        let started = UNIX_EPOCH + Duration::from_millis(read_u64(&header[5..13]));
+
+        let mut chunks = vec![];
+        let mut record = [0; 13];
+        loop {
+            match input.read(&mut record[..1]) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            try!(input.read_exact(&mut record[1..]));
+            let direction = match record[0] {
+                0 => Direction::Received,
+                1 => Direction::Sent,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown direction")),
+            };
+            let elapsed = Duration::from_micros(read_u64(&record[1..9]));
+            // Read through `take` rather than allocating `length` up front, a damaged file can
+            // claim up to 4GB.
+            let length = read_u32(&record[9..13]) as u64;
+            let mut data = vec![];
+            try!(input.by_ref().take(length).read_to_end(&mut data));
+            if data.len() as u64 != length {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunk"));
+            }
+            chunks.push(Chunk {
+                direction: direction,
+                elapsed: elapsed,
+                data: data,
+            });
+        }
+        Ok(Capture {
+            started: started,
+            chunks: chunks,
+        })
+    }
+
+    /// Treat a raw dump of relay output, like `weechat_parser/tests/fodder/simple.dat`, as a
+    /// capture with a single received chunk.
+    pub fn from_raw(data: Vec<u8>) -> Capture {
+        Capture {
+            started: UNIX_EPOCH,
+            chunks: vec![Chunk {
+                             direction: Direction::Received,
+                             elapsed: Duration::from_secs(0),
+                             data: data,
+                         }],
+        }
+    }
+
+    pub fn received<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Chunk> + 'a> {
+        Box::new(self.chunks.iter().filter(|chunk| chunk.direction == Direction::Received))
+    }
+
+    /// Call `send` with every received chunk, sleeping between chunks according to `speed`.
+    pub fn play<F>(&self, speed: Speed, mut send: F) -> io::Result<()>
+        where F: FnMut(&[u8]) -> io::Result<()>
+    {
+        let started = Instant::now();
+        for chunk in self.received() {
+            let due = speed.scale(chunk.elapsed);
+            let now = started.elapsed();
+            if due > now {
+                thread::sleep(due - now);
+            }
+            try!(send(&chunk.data));
+        }
+        Ok(())
+    }
+
+    /// Feed the received side of the capture through a new `weechat_parser` thread.
+    pub fn replay(&self, speed: Speed) -> Receiver<Result<WeechatMessage, WeechatParseError>> {
+        let (tx, rx) = weechat_parser::new();
+        let capture = self.clone();
+        thread::spawn(move || {
+            let _ = capture.play(speed, |data| {
+                tx.send(data.to_vec())
+                  .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "parser went away"))
+            });
+        });
+        rx
+    }
+}
+
+fn read_u32(buffer: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buffer[..4]);
+    u32::from_be_bytes(bytes)
+}
+
+fn read_u64(buffer: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buffer[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[test]
+fn test_capture_round_trip() {
+    let output = Arc::new(Mutex::new(vec![]));
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let recorder = Recorder::new(Shared(output.clone())).unwrap();
+    recorder.record(Direction::Sent, b"init\n").unwrap();
+    recorder.record(Direction::Received, &[0, 0, 0, 5]).unwrap();
+    recorder.record(Direction::Received, &[0]).unwrap();
+
+    let bytes = output.lock().unwrap().clone();
+    let capture = Capture::read_from(&bytes[..]).unwrap();
+    assert_eq!(capture.chunks.len(), 3);
+    assert_eq!(capture.chunks[0].direction, Direction::Sent);
+    assert_eq!(capture.chunks[0].data, b"init\n".to_vec());
+    assert_eq!(capture.chunks[1].direction, Direction::Received);
+    assert_eq!(capture.chunks[1].data, vec![0, 0, 0, 5]);
+    assert_eq!(capture.chunks[2].data, vec![0]);
+    assert!(capture.chunks[1].elapsed <= capture.chunks[2].elapsed);
+    assert_eq!(capture.received().count(), 2);
+}
+
+#[test]
+fn test_capture_rejects_truncated_chunk() {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&[VERSION, 0, 0, 0, 0, 0, 0, 0, 0]);
+    bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255]);
+    bytes.extend_from_slice(b"short");
+    let error = Capture::read_from(&bytes[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_capture_rejects_other_files() {
+    let error = Capture::read_from(&b"not a capture at all"[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,75 @@
+//! Everything `WeechatRelay::connect_with` needs to reach and log in to a relay.
+
+#[cfg(unix)]
+use std::path::PathBuf;
+use auth;
+use auth::{HashAlgorithm, Totp};
+#[cfg(feature = "tls")]
+use tls::TlsVerification;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RelayAddress {
+    /// `host:port` of a relay, plain or with TLS.
+    Tcp(String),
+    /// Path of a relay's Unix domain socket.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// A `ws://` or `wss://` URL, usually ending in `/weechat`.
+    WebSocket(String),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RelayConfig {
+    pub address: RelayAddress,
+    pub password: String,
+    pub totp: Option<Totp>,
+    /// Wrap TCP connections in TLS, or verify `wss://` URLs, this way. `wss://` URLs are checked
+    /// against the system roots when this is `None`.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsVerification>,
+    /// Password hash algorithms to offer in the handshake.
+    pub hash_algorithms: Vec<HashAlgorithm>,
+}
+
+impl RelayConfig {
+    pub fn new(address: RelayAddress, password: &str) -> RelayConfig {
+        RelayConfig {
+            address: address,
+            password: password.to_owned(),
+            totp: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            hash_algorithms: auth::ALL_ALGORITHMS.to_vec(),
+        }
+    }
+
+    pub fn tcp(address: &str, password: &str) -> RelayConfig {
+        RelayConfig::new(RelayAddress::Tcp(address.to_owned()), password)
+    }
+
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P, password: &str) -> RelayConfig {
+        RelayConfig::new(RelayAddress::Unix(path.into()), password)
+    }
+
+    pub fn websocket(url: &str, password: &str) -> RelayConfig {
+        RelayConfig::new(RelayAddress::WebSocket(url.to_owned()), password)
+    }
+}
+
+/// The host part of a `host:port` address, which TLS checks the certificate against.
+pub fn host_of(address: &str) -> &str {
+    let host = match address.rfind(':') {
+        Some(colon) if !address[colon..].contains(']') => &address[..colon],
+        _ => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[test]
+fn test_host_of() {
+    assert_eq!(host_of("relay.example.com:9001"), "relay.example.com");
+    assert_eq!(host_of("relay.example.com"), "relay.example.com");
+    assert_eq!(host_of("[::1]:9001"), "::1");
+    assert_eq!(host_of("127.0.0.1:9001"), "127.0.0.1");
+}
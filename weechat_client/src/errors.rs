@@ -0,0 +1,97 @@
+use std::error;
+use std::fmt;
+use std::io;
+use auth::AuthError;
+#[cfg(feature = "tls")]
+use tls::TlsError;
+use weechat_parser::errors::WeechatParseError;
+use websocket::WebSocketError;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Parse(WeechatParseError),
+    Auth(AuthError),
+    #[cfg(feature = "tls")]
+    Tls(TlsError),
+    WebSocket(WebSocketError),
+    /// The relay closed the connection.
+    Closed,
+    /// An argument can't be sent to the relay as it is, like a command spanning more than a
+    /// line.
+    InvalidArgument(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ClientError::Io(ref e) => write!(f, "io error: {}", e),
+            ClientError::Parse(ref e) => write!(f, "couldn't parse relay message: {}", e),
+            ClientError::Auth(ref e) => write!(f, "{}", e),
+            #[cfg(feature = "tls")]
+            ClientError::Tls(ref e) => write!(f, "{}", e),
+            ClientError::WebSocket(ref e) => write!(f, "{}", e),
+            ClientError::Closed => f.write_str("relay closed the connection"),
+            ClientError::InvalidArgument(ref reason) => write!(f, "invalid argument: {}", reason),
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn description(&self) -> &str {
+        match *self {
+            ClientError::Io(_) => "io error",
+            ClientError::Parse(_) => "couldn't parse relay message",
+            ClientError::Auth(_) => "authentication failed",
+            #[cfg(feature = "tls")]
+            ClientError::Tls(_) => "tls error",
+            ClientError::WebSocket(_) => "websocket error",
+            ClientError::Closed => "relay closed the connection",
+            ClientError::InvalidArgument(_) => "invalid argument",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            ClientError::Io(ref e) => Some(e),
+            ClientError::Parse(ref e) => Some(e),
+            ClientError::Auth(ref e) => Some(e),
+            #[cfg(feature = "tls")]
+            ClientError::Tls(ref e) => Some(e),
+            ClientError::WebSocket(ref e) => Some(e),
+            ClientError::Closed => None,
+            ClientError::InvalidArgument(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> ClientError {
+        ClientError::Io(error)
+    }
+}
+
+impl From<WeechatParseError> for ClientError {
+    fn from(error: WeechatParseError) -> ClientError {
+        ClientError::Parse(error)
+    }
+}
+
+impl From<AuthError> for ClientError {
+    fn from(error: AuthError) -> ClientError {
+        ClientError::Auth(error)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<TlsError> for ClientError {
+    fn from(error: TlsError) -> ClientError {
+        ClientError::Tls(error)
+    }
+}
+
+impl From<WebSocketError> for ClientError {
+    fn from(error: WebSocketError) -> ClientError {
+        ClientError::WebSocket(error)
+    }
+}
@@ -0,0 +1,144 @@
+//! Noticing a connection that died without being closed.
+//!
+//! After a laptop sleeps or a NAT forgets the connection, reads just time out forever. A
+//! `Keepalive` sends `ping` when the relay has been quiet for a while and declares the
+//! connection dead if the `_pong` doesn't come back in time, measuring latency on the way.
+
+use std::time::{Duration, Instant};
+use weechat_parser::{WeechatData, WeechatMessage};
+
+/// `ping` data of our own pings start with this, so they aren't confused with the caller's.
+const TOKEN_PREFIX: &'static str = "keepalive-";
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Heartbeat {
+    /// Ping after the relay has been quiet this long.
+    pub interval: Duration,
+    /// The connection is dead if the `_pong` takes longer than this.
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Heartbeat {
+        Heartbeat {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What to do for the connection next.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Action {
+    /// Nothing until `Instant`.
+    Wait(Instant),
+    /// Send `ping` with this data.
+    Ping(String),
+    /// No `_pong` came back within the timeout.
+    Dead,
+}
+
+/// Heartbeat state of one connection.
+pub struct Keepalive {
+    heartbeat: Heartbeat,
+    last_heard: Instant,
+    next_token: u64,
+    /// Data and send time of the ping waiting for its `_pong`.
+    outstanding: Option<(String, Instant)>,
+    latency: Option<Duration>,
+}
+
+impl Keepalive {
+    /// Start counting from `now`, when the connection was made.
+    pub fn new(heartbeat: Heartbeat, now: Instant) -> Keepalive {
+        Keepalive {
+            heartbeat: heartbeat,
+            last_heard: now,
+            next_token: 1,
+            outstanding: None,
+            latency: None,
+        }
+    }
+
+    /// Round trip time of the last answered ping.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Action {
+        if let Some((_, sent)) = self.outstanding {
+            let deadline = sent + self.heartbeat.timeout;
+            return if now >= deadline {
+                Action::Dead
+            } else {
+                Action::Wait(deadline)
+            };
+        }
+        let due = self.last_heard + self.heartbeat.interval;
+        if now < due {
+            return Action::Wait(due);
+        }
+        let token = format!("{}{}", TOKEN_PREFIX, self.next_token);
+        self.next_token += 1;
+        self.outstanding = Some((token.clone(), now));
+        Action::Ping(token)
+    }
+
+    /// Note that `message` arrived at `now`. Returns true if it is the `_pong` to one of our
+    /// pings, which the caller shouldn't see.
+    pub fn received(&mut self, message: &WeechatMessage, now: Instant) -> bool {
+        self.last_heard = now;
+        if message.id != "_pong" {
+            return false;
+        }
+        let data = match message.data.first() {
+            Some(&WeechatData::String(ref data)) => data,
+            _ => return false,
+        };
+        if !data.starts_with(TOKEN_PREFIX) {
+            return false;
+        }
+        if let Some((token, sent)) = self.outstanding.take() {
+            if &token == data {
+                self.latency = Some(now - sent);
+            } else {
+                self.outstanding = Some((token, sent));
+            }
+        }
+        true
+    }
+}
+
+#[test]
+fn test_keepalive() {
+    let heartbeat = Heartbeat {
+        interval: Duration::from_secs(30),
+        timeout: Duration::from_secs(10),
+    };
+    let start = Instant::now();
+    let at = |seconds: u64| start + Duration::from_secs(seconds);
+    let pong = |data: &str| {
+        WeechatMessage {
+            id: "_pong".to_owned(),
+            data: vec![WeechatData::String(data.to_owned())],
+        }
+    };
+    let mut keepalive = Keepalive::new(heartbeat, start);
+    assert_eq!(keepalive.poll(at(10)), Action::Wait(at(30)));
+    // Anything from the relay pushes the next ping back.
+    assert!(!keepalive.received(&WeechatMessage {
+                                    id: "_buffer_line_added".to_owned(),
+                                    data: vec![],
+                                },
+                                at(20)));
+    assert_eq!(keepalive.poll(at(30)), Action::Wait(at(50)));
+
+    assert_eq!(keepalive.poll(at(50)), Action::Ping("keepalive-1".to_owned()));
+    assert_eq!(keepalive.poll(at(52)), Action::Wait(at(60)));
+    assert!(!keepalive.received(&pong("mine"), at(52)));
+    assert!(keepalive.received(&pong("keepalive-1"), at(53)));
+    assert_eq!(keepalive.latency(), Some(Duration::from_secs(3)));
+
+    assert_eq!(keepalive.poll(at(83)), Action::Ping("keepalive-2".to_owned()));
+    assert_eq!(keepalive.poll(at(93)), Action::Dead);
+}
@@ -1,101 +1,598 @@
+extern crate hmac;
+extern crate pbkdf2;
+extern crate rand;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate rustls_native_certs;
+#[cfg(feature = "tls")]
+extern crate rustls_pemfile;
+extern crate sha1;
+extern crate sha2;
+extern crate tungstenite;
 extern crate weechat_parser;
 
-use std::io::prelude::*;
+pub mod auth;
+pub mod buffers;
+pub mod capture;
+pub mod config;
+pub mod errors;
+pub mod keepalive;
+pub mod mock;
+pub mod nicklist;
+pub mod requests;
+pub mod subscriptions;
+pub mod supervisor;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+pub mod websocket;
+
+use auth::{AuthError, Handshake, HashAlgorithm};
+pub use buffers::{Buffer, BufferStore};
+pub use config::{RelayAddress, RelayConfig};
+pub use errors::ClientError;
+pub use nicklist::{Nicklist, NicklistStore};
+pub use keepalive::Heartbeat;
+pub use subscriptions::Subscriptions;
+pub use supervisor::{Backoff, ConnectionState, Supervisor};
+use std::collections::VecDeque;
+use std::io;
 use std::net::{TcpStream, ToSocketAddrs};
-use std::time::Duration;
-use weechat_parser::WeechatData;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::{Duration, Instant};
+use weechat_parser::WeechatMessage;
+use weechat_parser::errors::ErrorKind::MalformedBinaryParse;
+use weechat_parser::errors::WeechatParseError;
+use weechat_parser::events::{Completion, LineAdded, RelayEvent};
+use requests::{IdAllocator, PendingRequests, Request};
+use subscriptions::{sync_command, SyncOption};
+use transport::Transport;
+use websocket::Endpoint;
+#[cfg(not(feature = "tls"))]
+use websocket::WebSocketError;
 
-macro_rules! println_stderr(
-    ($($arg:tt)*) => (
-        if let Err(x) = writeln!(&mut ::std::io::stderr(), $($arg)* ) {
-            panic!("Unable to write to stderr: {}", x);
-        }
-    )
-);
+/// How long to wait for the relay to answer `handshake` before assuming it predates it.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the `_pong` that confirms `init` was accepted.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fields of a line `LineAdded` needs.
+const LINE_KEYS: &'static str = "buffer,date,date_printed,displayed,highlight,tags_array,prefix,\
+                                 message";
 
+/// How often reads give up so timeouts can be checked.
+const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A connection to a relay. Commands that get a reply return a `Request` to `wait` on, everything
+/// else the relay sends, its events, comes out of `recv`, `messages` or `events`.
 pub struct WeechatRelay {
-    in_stream: TcpStream,
-    out_stream: TcpStream,
+    transport: Box<dyn Transport>,
+    buffer: Vec<u8>,
+    /// Messages read while waiting for something else, handed out before reading more.
+    queue: VecDeque<WeechatMessage>,
+    ids: IdAllocator,
+    pending: PendingRequests,
 }
 
 impl WeechatRelay {
-    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<WeechatRelay, String> {
-        if let Ok(out_stream) = TcpStream::connect(addr) {
-            out_stream.set_read_timeout(Some(Duration::from_millis(1000))).unwrap();
-            if let Ok(in_stream) = out_stream.try_clone() {
-                return Ok(WeechatRelay{
-                    in_stream: in_stream,
-                    out_stream: out_stream
-                })
+    /// Talk to the relay through `transport`, for connections `WeechatRelay` can't make itself.
+    pub fn new<T: Transport + 'static>(transport: T) -> WeechatRelay {
+        WeechatRelay {
+            transport: Box::new(transport),
+            buffer: vec![],
+            queue: VecDeque::new(),
+            ids: IdAllocator::new(),
+            pending: PendingRequests::new(),
+        }
+    }
+
+    /// Connect as `config` says and log in, retrying with the neighbouring TOTP windows when the
+    /// relay rejects a code computed from a secret, since our clock may be off from the relay's.
+    pub fn connect_with(config: &RelayConfig) -> Result<WeechatRelay, ClientError> {
+        WeechatRelay::connect_at(config, auth::unix_time())
+    }
+
+    /// `connect_with`, computing TOTP codes for the Unix time `now`.
+    fn connect_at(config: &RelayConfig, now: u64) -> Result<WeechatRelay, ClientError> {
+        let codes = config.totp.as_ref().map(|totp| totp.codes_at(now)).unwrap_or_default();
+        let mut attempt = 0;
+        loop {
+            let mut relay = try!(WeechatRelay::open(config));
+            let code = codes.get(attempt).map(|code| code.as_ref());
+            match relay.login_with(&config.password, code, &config.hash_algorithms) {
+                Ok(()) => return Ok(relay),
+                Err(ClientError::Auth(AuthError::Rejected)) if attempt + 1 < codes.len() => {
+                    attempt += 1
+                }
+                Err(e) => return Err(e),
             }
-        } else {
-            println_stderr!("couldn't connect");
         }
-        return Err("mooooo".to_owned())
     }
-}
 
-pub fn decode() {
-    let mut out_stream = TcpStream::connect("localhost:9000").unwrap();
-    let mut in_stream = out_stream.try_clone().unwrap();
-    in_stream.set_read_timeout(Some(Duration::from_millis(1000))).unwrap();
-    out_stream.write("init\n".as_bytes()).unwrap();
-    out_stream.write("sync\n".as_bytes()).unwrap();
-    let (tx, rx) = weechat_parser::new();
-    loop {
-        let mut buffer = Vec::<u8>::with_capacity(150);
-        match in_stream.read_to_end(&mut buffer) {
-            Ok(count) => {
-                if count > 0 {
-                    println_stderr!("sending {}", buffer.len());
+    /// Open the connection `config` describes without logging in.
+    fn open(config: &RelayConfig) -> Result<WeechatRelay, ClientError> {
+        match config.address {
+            #[cfg(feature = "tls")]
+            RelayAddress::Tcp(ref address) if config.tls.is_some() => {
+                let verification = config.tls.as_ref().unwrap();
+                WeechatRelay::connect_tls(&address[..], config::host_of(address), verification)
+            }
+            RelayAddress::Tcp(ref address) => WeechatRelay::connect(&address[..]),
+            #[cfg(unix)]
+            RelayAddress::Unix(ref path) => WeechatRelay::connect_unix(path),
+            #[cfg(feature = "tls")]
+            RelayAddress::WebSocket(ref url) if config.tls.is_some() => {
+                WeechatRelay::connect_websocket_tls(url, config.tls.as_ref().unwrap())
+            }
+            RelayAddress::WebSocket(ref url) => WeechatRelay::connect_websocket(url),
+        }
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<WeechatRelay, ClientError> {
+        let stream = try!(TcpStream::connect(addr));
+        try!(stream.set_read_timeout(Some(READ_TIMEOUT)));
+        Ok(WeechatRelay::new(stream))
+    }
+
+    /// Connect to a relay listening on a Unix domain socket, or an `ssh -L` forward of one.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<WeechatRelay, ClientError> {
+        let stream = try!(UnixStream::connect(path));
+        try!(stream.set_read_timeout(Some(READ_TIMEOUT)));
+        Ok(WeechatRelay::new(stream))
+    }
+
+    /// Connect to a relay with TLS enabled, checking its certificate as `verification` says.
+    /// `server_name` is the host name the certificate should be for.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls<A: ToSocketAddrs>(addr: A,
+                                         server_name: &str,
+                                         verification: &tls::TlsVerification)
+                                         -> Result<WeechatRelay, ClientError> {
+        let stream = try!(TcpStream::connect(addr));
+        try!(stream.set_read_timeout(Some(READ_TIMEOUT)));
+        let stream = try!(tls::connect(stream, server_name, verification));
+        Ok(WeechatRelay::new(stream))
+    }
+
+    /// Connect to a relay over WebSocket, like `ws://host:port/weechat`. `wss://` URLs are
+    /// checked against the system roots, use `connect_websocket_tls` to verify them differently.
+    pub fn connect_websocket(url: &str) -> Result<WeechatRelay, ClientError> {
+        let endpoint = try!(Endpoint::from_url(url));
+        if endpoint.secure {
+            return WeechatRelay::connect_secure_websocket(url);
+        }
+        let stream = try!(TcpStream::connect((endpoint.host.as_ref(), endpoint.port)));
+        try!(stream.set_read_timeout(Some(READ_TIMEOUT)));
+        let stream = try!(websocket::upgrade(url, stream));
+        Ok(WeechatRelay::new(stream))
+    }
+
+    #[cfg(feature = "tls")]
+    fn connect_secure_websocket(url: &str) -> Result<WeechatRelay, ClientError> {
+        WeechatRelay::connect_websocket_tls(url, &tls::TlsVerification::SystemRoots)
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn connect_secure_websocket(url: &str) -> Result<WeechatRelay, ClientError> {
+        Err(ClientError::WebSocket(WebSocketError::Url(format!("{} needs the tls feature", url))))
+    }
+
+    /// Connect to a relay over WebSocket with TLS, checking its certificate as `verification`
+    /// says.
+    #[cfg(feature = "tls")]
+    pub fn connect_websocket_tls(url: &str,
+                                 verification: &tls::TlsVerification)
+                                 -> Result<WeechatRelay, ClientError> {
+        let endpoint = try!(Endpoint::from_url(url));
+        let stream = try!(TcpStream::connect((endpoint.host.as_ref(), endpoint.port)));
+        try!(stream.set_read_timeout(Some(READ_TIMEOUT)));
+        let stream = try!(tls::connect(stream, &endpoint.host, verification));
+        let stream = try!(websocket::upgrade(url, stream));
+        Ok(WeechatRelay::new(stream))
+    }
+
+    /// Negotiate a password hash with `handshake` and log in with `init`, offering every
+    /// algorithm we support.
+    pub fn login(&mut self, password: &str) -> Result<(), ClientError> {
+        self.login_with(password, None, &auth::ALL_ALGORITHMS)
+    }
+
+    /// Like `login`, sending the `totp` code if given and only offering `algorithms`.
+    pub fn login_with(&mut self,
+                      password: &str,
+                      totp: Option<&str>,
+                      algorithms: &[HashAlgorithm])
+                      -> Result<(), ClientError> {
+        try!(self.send(&auth::handshake_command(algorithms)));
+        let handshake = match try!(self.wait_for("handshake", HANDSHAKE_TIMEOUT)) {
+            Some(message) => Some(try!(Handshake::from_message(&message))),
+            None => None,
+        };
+        if let Some(ref handshake) = handshake {
+            if !algorithms.contains(&handshake.algorithm) {
+                let name = handshake.algorithm.name().to_owned();
+                return Err(ClientError::Auth(AuthError::UnsupportedAlgorithm(name)));
+            }
+            if handshake.totp && totp.is_none() {
+                return Err(ClientError::Auth(AuthError::TotpRequired));
+            }
+        }
+        let command = auth::init_command(handshake.as_ref(), password, totp);
+        self.confirm_init(&command)
+    }
+
+    /// Send `init` with a plain text password and no handshake, for relays older than 2.9.
+    pub fn init(&mut self, password: &str, totp: Option<&str>) -> Result<(), ClientError> {
+        let command = auth::init_command(None, password, totp);
+        self.confirm_init(&command)
+    }
+
+    /// The relay doesn't answer a bad `init`, it just closes the connection, so a `ping` is sent
+    /// behind it and the `_pong` confirms the login.
+    fn confirm_init(&mut self, command: &str) -> Result<(), ClientError> {
+        try!(self.send(command));
+        try!(self.send("ping login\n"));
+        match self.wait_for("_pong", LOGIN_TIMEOUT) {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) | Err(ClientError::Closed) => Err(ClientError::Auth(AuthError::Rejected)),
+            Err(ClientError::Io(ref e)) if is_disconnect(e) => {
+                Err(ClientError::Auth(AuthError::Rejected))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Start receiving events for `buffers`, full names or pointers, or every buffer if empty.
+    pub fn sync(&mut self, buffers: &[&str]) -> Result<(), ClientError> {
+        self.sync_with(buffers, &[])
+    }
+
+    /// Like `sync`, only receiving what `options` say, or everything if empty.
+    pub fn sync_with(&mut self,
+                     buffers: &[&str],
+                     options: &[SyncOption])
+                     -> Result<(), ClientError> {
+        self.send(&sync_command("sync", buffers, options))
+    }
+
+    /// Stop receiving events for `buffers`, or every buffer if empty.
+    pub fn desync(&mut self, buffers: &[&str]) -> Result<(), ClientError> {
+        self.desync_with(buffers, &[])
+    }
+
+    /// Like `desync`, only for what `options` say, or everything if empty.
+    pub fn desync_with(&mut self,
+                       buffers: &[&str],
+                       options: &[SyncOption])
+                       -> Result<(), ClientError> {
+        self.send(&sync_command("desync", buffers, options))
+    }
+
+    /// Send `text` to `buffer` as a message, one `input` per line. Lines starting with `/` are
+    /// sent as they read instead of being run as commands.
+    pub fn send_input(&mut self, buffer: &str, text: &str) -> Result<(), ClientError> {
+        self.send(&input_commands(buffer, text))
+    }
+
+    /// Run `command` in `buffer`, like `/part` or `me waves`, the `/` being optional.
+    pub fn send_command(&mut self, buffer: &str, command: &str) -> Result<(), ClientError> {
+        if command.contains(|c| c == '\n' || c == '\r') {
+            let reason = "command spans more than a line".to_owned();
+            return Err(ClientError::InvalidArgument(reason));
+        }
+        let command = command.trim_start_matches('/');
+        self.send(&format!("input {} /{}\n", buffer, command))
+    }
+
+    /// Send `command` with a fresh id, like `hdata buffer:gui_buffers(*) number`, and return the
+    /// request to `wait` on for its reply.
+    pub fn request(&mut self, command: &str) -> Result<Request, ClientError> {
+        let request = self.ids.allocate();
+        try!(self.send(&format!("({}) {}\n", request.id(), command)));
+        self.pending.insert(&request);
+        Ok(request)
+    }
+
+    /// Ask for an hdata, like `buffer:gui_buffers(*) number,full_name`.
+    pub fn hdata(&mut self, path_and_keys: &str) -> Result<Request, ClientError> {
+        self.request(&format!("hdata {}", path_and_keys))
+    }
+
+    /// The last `count` lines of the buffer at `pointer`, oldest first. Pointers of every buffer
+    /// are in the `BufferStore`. Empty buffers and pointers to buffers that are gone have no
+    /// lines.
+    pub fn last_lines(&mut self,
+                      pointer: &str,
+                      count: u32)
+                      -> Result<Vec<LineAdded>, ClientError> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let request = try!(self.hdata(&format!("buffer:{}/own_lines/last_line(-{})/data {}",
+                                               pointer,
+                                               count,
+                                               LINE_KEYS)));
+        let reply = try!(self.wait(request));
+        // The relay walks back from the last line.
+        let mut lines = try!(LineAdded::all_from(&reply));
+        lines.reverse();
+        Ok(lines)
+    }
+
+    /// Complete the word at `position` in `data`, or at its end if `None`, as if tab was pressed
+    /// in `buffer`, a full name or pointer. `None` if there is nothing to complete.
+    pub fn completion(&mut self,
+                      buffer: &str,
+                      position: Option<usize>,
+                      data: &str)
+                      -> Result<Option<Completion>, ClientError> {
+        if data.contains(|c| c == '\n' || c == '\r') {
+            let reason = "completion data spans more than a line".to_owned();
+            return Err(ClientError::InvalidArgument(reason));
+        }
+        let position = position.map(|position| position as i64).unwrap_or(-1);
+        let request = try!(self.request(&format!("completion {} {} {}", buffer, position, data)));
+        let reply = try!(self.wait(request));
+        Ok(try!(Completion::from_message(&reply)))
+    }
+
+    pub fn info(&mut self, name: &str) -> Result<Request, ClientError> {
+        self.request(&format!("info {}", name))
+    }
+
+    pub fn infolist(&mut self,
+                    name: &str,
+                    pointer: Option<&str>,
+                    arguments: Option<&str>)
+                    -> Result<Request, ClientError> {
+        let mut command = format!("infolist {}", name);
+        if pointer.is_some() || arguments.is_some() {
+            command.push_str(&format!(" {}", pointer.unwrap_or("0x0")));
+        }
+        if let Some(arguments) = arguments {
+            command.push_str(&format!(" {}", arguments));
+        }
+        self.request(&command)
+    }
+
+    /// Ask for the nicklist of `buffer`, or of every buffer.
+    pub fn nicklist(&mut self, buffer: Option<&str>) -> Result<Request, ClientError> {
+        match buffer {
+            Some(buffer) => self.request(&format!("nicklist {}", buffer)),
+            None => self.request("nicklist"),
+        }
+    }
+
+    /// Block until the reply to `request` arrives. Events read in the meantime are kept for
+    /// `recv`, and replies to other requests for their own `wait`.
+    pub fn wait(&mut self, request: Request) -> Result<WeechatMessage, ClientError> {
+        loop {
+            if let Some(reply) = try!(self.wait_timeout(&request, READ_TIMEOUT)) {
+                return Ok(reply);
+            }
+        }
+    }
+
+    /// Like `wait`, giving up after `timeout`. The request stays pending so it can be waited on
+    /// again.
+    pub fn wait_timeout(&mut self,
+                        request: &Request,
+                        timeout: Duration)
+                        -> Result<Option<WeechatMessage>, ClientError> {
+        let started = Instant::now();
+        loop {
+            if let Some(reply) = self.pending.take(request) {
+                return Ok(Some(reply));
+            }
+            if let Some(message) = try!(self.read_message()) {
+                if let Some(event) = self.pending.resolve(message) {
+                    self.queue.push_back(event);
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Ok(self.pending.take(request));
+            }
+        }
+    }
+
+    /// Stop waiting for `request`, its reply is dropped when it arrives.
+    pub fn cancel(&mut self, request: Request) {
+        self.pending.cancel(&request);
+    }
+
+    /// The relay answers with a `_pong` carrying `data`.
+    pub fn ping(&mut self, data: &str) -> Result<(), ClientError> {
+        self.send(&format!("ping {}\n", data))
+    }
+
+    /// Say goodbye to the relay and close the transport.
+    pub fn quit(&mut self) -> Result<(), ClientError> {
+        try!(self.send("quit\n"));
+        try!(self.transport.close());
+        Ok(())
+    }
+
+    /// Wait for the next message from the relay.
+    pub fn recv(&mut self) -> Result<WeechatMessage, ClientError> {
+        loop {
+            if let Some(message) = try!(self.recv_timeout(READ_TIMEOUT)) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next message from the relay.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<WeechatMessage>, ClientError> {
+        if let Some(message) = self.queue.pop_front() {
+            return Ok(Some(message));
+        }
+        let started = Instant::now();
+        loop {
+            if let Some(message) = try!(self.read_message()) {
+                if let Some(event) = self.pending.resolve(message) {
+                    return Ok(Some(event));
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Messages from the relay until it closes the connection.
+    pub fn messages<'a>(&'a mut self) -> Messages<'a> {
+        Messages { relay: self }
+    }
+
+    /// Like `messages`, decoded into `RelayEvent`s.
+    pub fn events<'a>(&'a mut self) -> Events<'a> {
+        Events { relay: self }
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), ClientError> {
+        try!(self.transport.write_command(command));
+        Ok(())
+    }
+
+    /// Read the next complete message, or `None` if the read timed out first.
+    fn read_message(&mut self) -> Result<Option<WeechatMessage>, ClientError> {
+        loop {
+            if self.buffer.len() >= 4 {
+                let length = try!(weechat_parser::get_length(&self.buffer)) as usize;
+                if length < 5 {
+                    // Not even the length and compression fit, we lost track of where messages
+                    // start and everything after this is garbage.
+                    self.buffer.clear();
+                    let _ = self.transport.close();
+                    let error = WeechatParseError::from((MalformedBinaryParse,
+                                                         "Message length is too short",
+                                                         format!("{}", length)));
+                    return Err(ClientError::Parse(error));
                 }
+                if self.buffer.len() >= length {
+                    // Split the message off first so one that doesn't parse is dropped.
+                    let rest = self.buffer.split_off(length);
+                    let raw = std::mem::replace(&mut self.buffer, rest);
+                    return Ok(Some(try!(WeechatMessage::from_raw_message(&raw))));
+                }
+            }
+            let mut chunk = [0; 4096];
+            match self.transport.read_bytes(&mut chunk) {
+                Ok(0) => return Err(ClientError::Closed),
+                Ok(count) => self.buffer.extend_from_slice(&chunk[..count]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                              e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(ClientError::Io(e)),
             }
-            Err(e) => {}
-        }
-        println_stderr!("received chunk of size: {:?}", buffer.len());
-        match tx.send(buffer) {
-            Ok(_) => loop {
-                match rx.try_recv() {
-                    Ok(res) => match res {
-                        Ok(message) => {
-                            if message.id == "_buffer_line_added" {
-                                if let &WeechatData::Hdata(_, _, ref data) = message.data.get(0).unwrap() {
-                                    for body in data {
-                                        if let &WeechatData::Char(ref highlight) = body.get("highlight").unwrap() {
-                                            if highlight == &'\u{1}' {
-                                                println_stderr!("Got message: {:?}",
-                                                                body.get("message").unwrap());
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            println_stderr!("error parsing {:?}", e);
-                            break;
-                        }
-                    },
-                    Err(_) => {
-                        break;
-                    }
+        }
+    }
+
+    /// Read until a message with `id` arrives, queueing anything else for `recv`.
+    fn wait_for(&mut self, id: &str, timeout: Duration) -> Result<Option<WeechatMessage>, ClientError> {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            if let Some(message) = try!(self.read_message()) {
+                if message.id == id {
+                    return Ok(Some(message));
+                }
+                if let Some(event) = self.pending.resolve(message) {
+                    self.queue.push_back(event);
                 }
-            },
-            Err(e) => {
-                println_stderr!("error parsing {:?}", e);
-                break;
             }
         }
+        Ok(None)
+    }
+}
 
+pub struct Messages<'a> {
+    relay: &'a mut WeechatRelay,
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<WeechatMessage, ClientError>;
+
+    fn next(&mut self) -> Option<Result<WeechatMessage, ClientError>> {
+        match self.relay.recv() {
+            Err(ClientError::Closed) => None,
+            result => Some(result),
+        }
     }
+}
 
-    println_stderr!("done reading");
-    out_stream.write("quit\n".as_bytes()).unwrap();
+pub struct Events<'a> {
+    relay: &'a mut WeechatRelay,
 }
 
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<RelayEvent, ClientError>;
+
+    fn next(&mut self) -> Option<Result<RelayEvent, ClientError>> {
+        match self.relay.recv() {
+            Err(ClientError::Closed) => None,
+            Err(e) => Some(Err(e)),
+            Ok(message) => Some(RelayEvent::from_message(message).map_err(ClientError::Parse)),
+        }
+    }
+}
+
+/// An `input` per line of `text`, with WeeChat's `//` escape for lines that would otherwise run
+/// as commands. Empty lines are skipped, WeeChat ignores them anyway.
+fn input_commands(buffer: &str, text: &str) -> String {
+    let mut commands = String::new();
+    for line in text.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.is_empty() {
+            continue;
+        }
+        let escape = if line.starts_with('/') { "/" } else { "" };
+        commands.push_str(&format!("input {} {}{}\n", buffer, escape, line));
+    }
+    commands
+}
+
+fn is_disconnect(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::BrokenPipe => true,
+        _ => false,
+    }
+}
 
 #[test]
 fn it_works() {
-    WeechatRelay::connect("127.0.0.1:9000").unwrap();
-    decode();
+    let relay = mock::MockRelay::start().unwrap();
+    WeechatRelay::connect(relay.address()).unwrap();
+}
+
+#[test]
+fn test_totp_clock_skew() {
+    // Halfway through a TOTP window, so the codes don't depend on when the test runs.
+    let now = 1_700_000_010;
+    let relay = mock::MockRelay::start().unwrap();
+    relay.require_password("secret");
+    relay.require_totp(b"12345678901234567890");
+    relay.set_time(Some(now));
+    relay.set_clock_offset(30);
+    let mut config = RelayConfig::tcp(&relay.address().to_string(), "secret");
+    config.totp = Some(auth::Totp::Secret(b"12345678901234567890".to_vec()));
+    WeechatRelay::connect_at(&config, now).unwrap();
+    // The current and previous windows are rejected before the next one gets in.
+    assert_eq!(relay.commands().iter().filter(|c| c.starts_with("init ")).count(), 3);
+}
+
+#[test]
+fn test_input_commands() {
+    assert_eq!(input_commands("core.weechat", "hello"), "input core.weechat hello\n");
+    assert_eq!(input_commands("irc.libera.#weechat", "first\r\n\n/second\n//third"),
+               "input irc.libera.#weechat first\n\
+                input irc.libera.#weechat //second\n\
+                input irc.libera.#weechat ///third\n");
+    assert_eq!(input_commands("core.weechat", "\n"), "");
 }
@@ -0,0 +1,566 @@
+//! An in-process relay server for testing clients without a running WeeChat.
+//!
+//! The mock listens on a random local port, records every command line it receives and answers
+//! with scripted `WeechatMessage`s. It can also require a password, in which case it negotiates a
+//! hash algorithm in `handshake` and closes the connection on a bad `init`, like the real relay.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use weechat_parser::{Compression, WeechatData, WeechatMessage};
+use auth;
+use auth::HashAlgorithm;
+use capture::{Capture, Speed};
+
+pub struct MockRelay {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<MockState>,
+    received: Condvar,
+}
+
+struct MockState {
+    running: bool,
+    /// Record commands but neither answer nor close, like a relay behind a dead connection.
+    frozen: bool,
+    compression: Compression,
+    commands: Vec<String>,
+    responses: HashMap<String, Vec<WeechatMessage>>,
+    sync_messages: Vec<WeechatMessage>,
+    clients: Vec<TcpStream>,
+    password: Option<String>,
+    hash_algorithms: Vec<HashAlgorithm>,
+    iterations: u32,
+    nonce: Vec<u8>,
+    totp_secret: Option<Vec<u8>>,
+    totp_window: u64,
+    clock_offset: i64,
+    /// Fixed Unix time to check TOTP codes at, the system clock if `None`.
+    time: Option<u64>,
+}
+
+/// Per connection authentication state.
+struct Session {
+    authenticated: bool,
+    algorithm: Option<HashAlgorithm>,
+}
+
+/// A command line split into its optional `(id)`, name and arguments.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Command {
+    pub id: Option<String>,
+    pub name: String,
+    pub arguments: String,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Command {
+        let line = line.trim_end_matches(|c| c == '\n' || c == '\r');
+        let (id, rest) = if line.starts_with('(') {
+            match line.find(')') {
+                Some(end) => (Some(line[1..end].to_owned()), line[end + 1..].trim_start()),
+                None => (None, line),
+            }
+        } else {
+            (None, line)
+        };
+        let mut parts = rest.splitn(2, ' ');
+        let name = parts.next().unwrap_or("").to_owned();
+        let arguments = parts.next().unwrap_or("").to_owned();
+        Command {
+            id: id,
+            name: name,
+            arguments: arguments,
+        }
+    }
+}
+
+/// A `_buffer_line_added` event carrying only `message`, for tests that need some event.
+pub fn line_added(message: &str) -> WeechatMessage {
+    WeechatMessage {
+        id: "_buffer_line_added".to_owned(),
+        data: vec![WeechatData::String(message.to_owned())],
+    }
+}
+
+/// The `handshake` reply of a relay that takes the password in plain text.
+pub fn plain_handshake() -> WeechatMessage {
+    WeechatMessage {
+        id: "handshake".to_owned(),
+        data: vec![WeechatData::Hashtable("str".to_owned(),
+                                          "str".to_owned(),
+                                          vec![(WeechatData::String("password_hash_algo"
+                                                                        .to_owned()),
+                                                WeechatData::String("plain".to_owned()))])],
+    }
+}
+
+/// The `_pong` answering `ping data`.
+pub fn pong(data: &str) -> WeechatMessage {
+    WeechatMessage {
+        id: "_pong".to_owned(),
+        data: vec![WeechatData::String(data.to_owned())],
+    }
+}
+
+/// What a relay without a password answers to the command `line` when `login` sends it, for
+/// relays served by hand over transports the mock doesn't speak.
+pub fn login_reply(line: &str) -> Option<WeechatMessage> {
+    if line.starts_with("(handshake) handshake") {
+        Some(plain_handshake())
+    } else if line.starts_with("ping ") {
+        Some(pong(line[5..].trim()))
+    } else {
+        None
+    }
+}
+
+impl MockRelay {
+    pub fn start() -> io::Result<MockRelay> {
+        MockRelay::start_on("127.0.0.1:0".parse().unwrap())
+    }
+
+    /// Listen on `address`, like the address of a mock that was dropped to simulate the relay
+    /// going away and coming back.
+    pub fn start_on(address: SocketAddr) -> io::Result<MockRelay> {
+        let listener = try!(TcpListener::bind(address));
+        let address = try!(listener.local_addr());
+        let shared = Arc::new(Shared {
+            state: Mutex::new(MockState {
+                running: true,
+                frozen: false,
+                compression: Compression::None,
+                commands: vec![],
+                responses: HashMap::new(),
+                sync_messages: vec![],
+                clients: vec![],
+                password: None,
+                hash_algorithms: auth::ALL_ALGORITHMS.to_vec(),
+                iterations: 1000,
+                nonce: vec![0x85, 0xb1, 0xee, 0x00, 0x69, 0x5a, 0x5b, 0x25, 0x4e, 0x14, 0xf4,
+                            0x88, 0x55, 0x38, 0xdf, 0x0d],
+                totp_secret: None,
+                totp_window: 0,
+                clock_offset: 0,
+                time: None,
+            }),
+            received: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let accept_thread = thread::spawn(move || accept_clients(listener, thread_shared));
+        Ok(MockRelay {
+            address: address,
+            shared: shared,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Compression used for every message the mock sends, defaults to `Compression::None`.
+    pub fn set_compression(&self, compression: Compression) {
+        self.shared.state.lock().unwrap().compression = compression;
+    }
+
+    /// Require clients to log in with `password` before any other command is answered.
+    pub fn require_password(&self, password: &str) {
+        self.shared.state.lock().unwrap().password = Some(password.to_owned());
+    }
+
+    /// Hash algorithms the mock accepts, like `relay.network.password_hash_algo`.
+    pub fn set_hash_algorithms(&self, algorithms: Vec<HashAlgorithm>) {
+        self.shared.state.lock().unwrap().hash_algorithms = algorithms;
+    }
+
+    /// Iterations for the PBKDF2 algorithms, like `relay.network.password_hash_iterations`.
+    pub fn set_iterations(&self, iterations: u32) {
+        self.shared.state.lock().unwrap().iterations = iterations;
+    }
+
+    /// Require a TOTP code computed from `secret` in `init`, like `relay.network.totp_secret`.
+    pub fn require_totp(&self, secret: &[u8]) {
+        self.shared.state.lock().unwrap().totp_secret = Some(secret.to_vec());
+    }
+
+    /// How many windows either side of the current one are accepted, like
+    /// `relay.network.totp_window`.
+    pub fn set_totp_window(&self, window: u64) {
+        self.shared.state.lock().unwrap().totp_window = window;
+    }
+
+    /// Pretend the mock's clock is `seconds` ahead (or behind, if negative) of the real one.
+    pub fn set_clock_offset(&self, seconds: i64) {
+        self.shared.state.lock().unwrap().clock_offset = seconds;
+    }
+
+    /// Check TOTP codes at the Unix time `time` instead of now, so tests don't depend on when
+    /// they run. The clock offset still applies.
+    pub fn set_time(&self, time: Option<u64>) {
+        self.shared.state.lock().unwrap().time = time;
+    }
+
+    /// Stop answering, without closing connections, until unfrozen.
+    pub fn set_frozen(&self, frozen: bool) {
+        self.shared.state.lock().unwrap().frozen = frozen;
+    }
+
+    /// Reply with `messages` every time a command named `command` is received. When the command
+    /// carries an `(id)` the replies are sent with that id, like the real relay does.
+    pub fn respond_to(&self, command: &str, messages: Vec<WeechatMessage>) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.responses.insert(command.to_owned(), messages);
+    }
+
+    /// Messages sent to a client after it sends `sync`.
+    pub fn on_sync(&self, messages: Vec<WeechatMessage>) {
+        self.shared.state.lock().unwrap().sync_messages = messages;
+    }
+
+    /// Send an unsolicited message to every connected client.
+    pub fn send(&self, message: &WeechatMessage) -> io::Result<()> {
+        let compression = self.shared.state.lock().unwrap().compression;
+        let bytes = try!(message.to_bytes(compression)
+                                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+        self.send_raw(&bytes)
+    }
+
+    /// Send raw bytes to every connected client. Clients that can't be written to anymore are
+    /// forgotten.
+    pub fn send_raw(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.clients.retain(|client| (&*client).write_all(bytes).is_ok());
+        Ok(())
+    }
+
+    /// Send the received side of `capture` to every connected client, blocking until done.
+    pub fn replay(&self, capture: &Capture, speed: Speed) -> io::Result<()> {
+        capture.play(speed, |data| self.send_raw(data))
+    }
+
+    /// Every command line received so far, without the trailing newline.
+    pub fn commands(&self) -> Vec<String> {
+        self.shared.state.lock().unwrap().commands.clone()
+    }
+
+    /// Block until a command named `name` has been received, or `timeout` passes.
+    pub fn wait_for_command(&self, name: &str, timeout: Duration) -> Option<Command> {
+        self.wait_until(timeout, |commands| {
+            commands.iter()
+                    .map(|line| Command::parse(line))
+                    .find(|command| command.name == name)
+        })
+    }
+
+    /// Block until the exact command line `line` has been received, or `timeout` passes.
+    /// Returns whether it was.
+    pub fn wait_for_line(&self, line: &str, timeout: Duration) -> bool {
+        self.wait_until(timeout, |commands| commands.iter().position(|command| command == line))
+            .is_some()
+    }
+
+    /// Wait for `found` to find something in the commands received so far.
+    fn wait_until<T, F>(&self, timeout: Duration, found: F) -> Option<T>
+        where F: Fn(&[String]) -> Option<T>
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(found) = found(&state.commands) {
+                return Some(found);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = self.shared.received.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Drop every connected client, as if the relay had gone away.
+    pub fn disconnect_all(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        for client in state.clients.drain(..) {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().running = false;
+        self.disconnect_all();
+        // Wake up the accept loop so it notices we are done.
+        let _ = TcpStream::connect(self.address);
+        // The port is free again once the accept loop has dropped the listener.
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+    }
+}
+
+fn accept_clients(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if !shared.state.lock().unwrap().running {
+            return;
+        }
+        if let Ok(stream) = stream {
+            if let Ok(clone) = stream.try_clone() {
+                shared.state.lock().unwrap().clients.push(clone);
+                let client_shared = shared.clone();
+                thread::spawn(move || handle_client(stream, client_shared));
+            }
+        }
+    }
+}
+
+fn handle_client(stream: TcpStream, shared: Arc<Shared>) {
+    let closer = stream.try_clone();
+    serve_client(stream, &shared);
+    // Our copy in `clients` keeps the socket open, close it so the client sees we are done. The
+    // next send to it then fails and forgets it.
+    if let Ok(closer) = closer {
+        let _ = closer.shutdown(Shutdown::Both);
+    }
+}
+
+fn serve_client(stream: TcpStream, shared: &Shared) {
+    let mut out_stream = match stream.try_clone() {
+        Ok(out_stream) => out_stream,
+        Err(_) => return,
+    };
+    let mut session = Session {
+        authenticated: false,
+        algorithm: None,
+    };
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let command = Command::parse(&line);
+        let replies = {
+            let mut state = shared.state.lock().unwrap();
+            state.commands.push(line.clone());
+            shared.received.notify_all();
+            if state.frozen {
+                continue;
+            }
+            match command.name.as_ref() {
+                "handshake" => handshake_reply(&state, &command, &mut session),
+                "init" => {
+                    session.authenticated = check_init(&state, &command, &session);
+                    if !session.authenticated {
+                        let _ = out_stream.shutdown(Shutdown::Both);
+                        return;
+                    }
+                    vec![]
+                }
+                "quit" => {
+                    let _ = out_stream.shutdown(Shutdown::Both);
+                    return;
+                }
+                // The relay ignores everything but handshake and init until logged in.
+                _ if state.password.is_some() && !session.authenticated => vec![],
+                _ => replies_for(&state, &command),
+            }
+        };
+        for reply in replies {
+            if out_stream.write_all(&reply).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn handshake_reply(state: &MockState, command: &Command, session: &mut Session) -> Vec<Vec<u8>> {
+    let offered: Vec<HashAlgorithm> = split_options(&command.arguments)
+                                          .into_iter()
+                                          .filter(|&(ref key, _)| key == "password_hash_algo")
+                                          .flat_map(|(_, value)| {
+                                              value.split(':')
+                                                   .filter_map(HashAlgorithm::from_name)
+                                                   .collect::<Vec<_>>()
+                                          })
+                                          .collect();
+    // The relay picks the strongest algorithm both sides allow.
+    let algorithm = auth::ALL_ALGORITHMS.iter()
+                                        .rev()
+                                        .cloned()
+                                        .find(|algorithm| {
+                                            offered.contains(algorithm) &&
+                                            state.hash_algorithms.contains(algorithm)
+                                        });
+    session.algorithm = algorithm;
+
+    let entry = |key: &str, value: String| {
+        (WeechatData::String(key.to_owned()), WeechatData::String(value))
+    };
+    let message = WeechatMessage {
+        id: command.id.clone().unwrap_or("handshake".to_owned()),
+        data: vec![WeechatData::Hashtable("str".to_owned(),
+                                          "str".to_owned(),
+                                          vec![entry("password_hash_algo",
+                                                     algorithm.map(|a| a.name())
+                                                              .unwrap_or("")
+                                                              .to_owned()),
+                                               entry("password_hash_iterations",
+                                                     state.iterations.to_string()),
+                                               entry("totp",
+                                                     if state.totp_secret.is_some() {
+                                                         "on".to_owned()
+                                                     } else {
+                                                         "off".to_owned()
+                                                     }),
+                                               entry("nonce",
+                                                     auth::to_hex(&state.nonce).to_uppercase()),
+                                               entry("compression", "off".to_owned())])],
+    };
+    message.to_bytes(state.compression).into_iter().collect()
+}
+
+fn check_init(state: &MockState, command: &Command, session: &Session) -> bool {
+    let options = split_options(&command.arguments);
+    let option = |name: &str| {
+        options.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.clone())
+    };
+    if let Some(ref secret) = state.totp_secret {
+        if !check_totp(state, secret, option("totp")) {
+            return false;
+        }
+    }
+    let password = match state.password {
+        Some(ref password) => password,
+        None => return true,
+    };
+
+    if let Some(plain) = option("password") {
+        let allowed = match session.algorithm {
+            Some(algorithm) => algorithm == HashAlgorithm::Plain,
+            None => state.hash_algorithms.contains(&HashAlgorithm::Plain),
+        };
+        return allowed && &plain == password;
+    }
+
+    let hashed = match option("password_hash") {
+        Some(hashed) => hashed,
+        None => return false,
+    };
+    let parts: Vec<&str> = hashed.split(':').collect();
+    let algorithm = match (parts.get(0).and_then(|name| HashAlgorithm::from_name(name)),
+                           session.algorithm) {
+        (Some(sent), Some(negotiated)) if sent == negotiated => sent,
+        _ => return false,
+    };
+    let (salt, iterations, hash) = match (algorithm, parts.len()) {
+        (HashAlgorithm::Pbkdf2Sha256, 4) |
+        (HashAlgorithm::Pbkdf2Sha512, 4) => {
+            (parts[1], parts[2].parse().unwrap_or(0), parts[3])
+        }
+        (HashAlgorithm::Sha256, 3) |
+        (HashAlgorithm::Sha512, 3) => (parts[1], 0, parts[2]),
+        _ => return false,
+    };
+    let salt = match auth::from_hex(salt) {
+        Some(salt) => salt,
+        None => return false,
+    };
+    let expected_iterations = match algorithm {
+        HashAlgorithm::Pbkdf2Sha256 | HashAlgorithm::Pbkdf2Sha512 => state.iterations,
+        _ => 0,
+    };
+    salt.starts_with(&state.nonce) && iterations == expected_iterations &&
+    auth::from_hex(hash) == Some(auth::hash_password(algorithm, &salt, iterations, password))
+}
+
+fn check_totp(state: &MockState, secret: &[u8], code: Option<String>) -> bool {
+    let code = match code {
+        Some(code) => code,
+        None => return false,
+    };
+    let now = state.time.unwrap_or_else(auth::unix_time) as i64 + state.clock_offset;
+    let window = state.totp_window as i64;
+    (-window..window + 1).any(|step| {
+        let time = now + step * auth::TOTP_PERIOD as i64;
+        time >= 0 && auth::totp_code(secret, time as u64, auth::TOTP_DIGITS) == code
+    })
+}
+
+/// Split `key=value` options separated by unescaped commas.
+fn split_options(arguments: &str) -> Vec<(String, String)> {
+    let mut options = vec![];
+    let mut current = String::new();
+    let mut chars = arguments.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            ',' => options.push(current.split_off(0)),
+            _ => current.push(c),
+        }
+    }
+    options.push(current);
+    options.into_iter()
+           .filter(|option| !option.is_empty())
+           .map(|option| {
+               let mut parts = option.splitn(2, '=');
+               (parts.next().unwrap_or("").to_owned(), parts.next().unwrap_or("").to_owned())
+           })
+           .collect()
+}
+
+fn replies_for(state: &MockState, command: &Command) -> Vec<Vec<u8>> {
+    let messages = match command.name.as_ref() {
+        "ping" => vec![pong(&command.arguments)],
+        "sync" => state.sync_messages.clone(),
+        name => {
+            let mut messages = state.responses.get(name).cloned().unwrap_or_default();
+            if let Some(ref id) = command.id {
+                for message in &mut messages {
+                    message.id = id.clone();
+                }
+            }
+            messages
+        }
+    };
+    messages.iter()
+            .filter_map(|message| message.to_bytes(state.compression).ok())
+            .collect()
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(Command::parse("init password=foo\n"),
+               Command {
+                   id: None,
+                   name: "init".to_owned(),
+                   arguments: "password=foo".to_owned(),
+               });
+    assert_eq!(Command::parse("(buffers) hdata buffer:gui_buffers(*) number"),
+               Command {
+                   id: Some("buffers".to_owned()),
+                   name: "hdata".to_owned(),
+                   arguments: "buffer:gui_buffers(*) number".to_owned(),
+               });
+    assert_eq!(Command::parse("quit"),
+               Command {
+                   id: None,
+                   name: "quit".to_owned(),
+                   arguments: "".to_owned(),
+               });
+}
@@ -0,0 +1,255 @@
+//! Nicklists of buffers, kept current from `_nicklist` and `_nicklist_diff`.
+//!
+//! A nicklist is a tree of groups holding nicks, sent as a flat list in tree order: a group comes
+//! before everything in it. `_nicklist` replaces the whole list of the buffers it covers,
+//! `_nicklist_diff` names a parent group with `^` and then adds (`+`), removes (`-`) or changes
+//! (`*`) items under it.
+
+use std::collections::HashMap;
+use weechat_parser::errors::WeechatParseError;
+use weechat_parser::events::{NicklistDiff, NicklistItem, RelayEvent};
+use weechat_parser::WeechatMessage;
+use {ClientError, WeechatRelay};
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Group {
+    pub pointer: String,
+    /// Groups the relay sorts carry a `NNN|` prefix, like `000|o` for IRC ops.
+    pub name: String,
+    pub color: String,
+    pub visible: bool,
+    /// 0 for the root group.
+    pub level: i32,
+    /// Pointer of the group this one is in, `None` for the root group.
+    pub parent: Option<String>,
+}
+
+impl Group {
+    /// `name` without the sorting prefix.
+    pub fn display_name(&self) -> &str {
+        match self.name.find('|') {
+            Some(bar) if self.name[..bar].chars().all(|c| c.is_digit(10)) => {
+                &self.name[bar + 1..]
+            }
+            _ => &self.name,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Nick {
+    pub pointer: String,
+    pub name: String,
+    pub color: String,
+    /// Like `@` for IRC ops, a space when there is none.
+    pub prefix: String,
+    pub prefix_color: String,
+    pub visible: bool,
+    /// Pointer of the group the nick is in.
+    pub group: String,
+}
+
+/// The nicklist of one buffer, in the order the relay sent it.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Nicklist {
+    groups: Vec<Group>,
+    nicks: Vec<Nick>,
+}
+
+impl Nicklist {
+    pub fn new() -> Nicklist {
+        Nicklist::default()
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    pub fn nicks(&self) -> &[Nick] {
+        &self.nicks
+    }
+
+    pub fn nick(&self, name: &str) -> Option<&Nick> {
+        self.nicks.iter().find(|nick| nick.name == name)
+    }
+
+    pub fn group(&self, pointer: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.pointer == pointer)
+    }
+
+    /// The group `nick` is in.
+    pub fn group_of(&self, nick: &Nick) -> Option<&Group> {
+        self.group(&nick.group)
+    }
+
+    /// Nicks directly in the group at `pointer`.
+    pub fn nicks_in(&self, pointer: &str) -> Vec<&Nick> {
+        self.nicks.iter().filter(|nick| nick.group == pointer).collect()
+    }
+
+    /// Add `item` under the group at `parent`.
+    fn add(&mut self, item: &NicklistItem, parent: Option<&str>) {
+        if item.group {
+            self.groups.push(Group {
+                pointer: item.pointer.clone(),
+                name: item.name.clone(),
+                color: item.color.clone(),
+                visible: item.visible,
+                level: item.level,
+                parent: parent.map(str::to_owned),
+            });
+        } else {
+            self.nicks.push(Nick {
+                pointer: item.pointer.clone(),
+                name: item.name.clone(),
+                color: item.color.clone(),
+                prefix: item.prefix.clone(),
+                prefix_color: item.prefix_color.clone(),
+                visible: item.visible,
+                group: parent.unwrap_or("").to_owned(),
+            });
+        }
+    }
+
+    /// Remove the item at `pointer`, and everything in it if it is a group.
+    fn remove(&mut self, pointer: &str) {
+        self.nicks.retain(|nick| nick.pointer != pointer);
+        let children: Vec<String> = self.groups
+                                        .iter()
+                                        .filter(|group| group.parent.as_deref() == Some(pointer))
+                                        .map(|group| group.pointer.clone())
+                                        .collect();
+        if self.groups.iter().any(|group| group.pointer == pointer) {
+            self.groups.retain(|group| group.pointer != pointer);
+            self.nicks.retain(|nick| nick.group != pointer);
+        }
+        for child in children {
+            self.remove(&child);
+        }
+    }
+
+    /// Take the fields of `item` for the item with the same pointer.
+    fn change(&mut self, item: &NicklistItem) {
+        let pointer = &item.pointer;
+        if item.group {
+            if let Some(group) = self.groups.iter_mut().find(|group| &group.pointer == pointer) {
+                group.name = item.name.clone();
+                group.color = item.color.clone();
+                group.visible = item.visible;
+                group.level = item.level;
+            }
+        } else if let Some(nick) = self.nicks.iter_mut().find(|nick| &nick.pointer == pointer) {
+            nick.name = item.name.clone();
+            nick.color = item.color.clone();
+            nick.prefix = item.prefix.clone();
+            nick.prefix_color = item.prefix_color.clone();
+            nick.visible = item.visible;
+        }
+    }
+}
+
+/// Nicklists by buffer pointer.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct NicklistStore {
+    buffers: HashMap<String, Nicklist>,
+}
+
+impl NicklistStore {
+    pub fn new() -> NicklistStore {
+        NicklistStore::default()
+    }
+
+    /// Ask `relay` for the nicklist of `buffer`, or of every buffer, and load it.
+    pub fn fetch(relay: &mut WeechatRelay,
+                 buffer: Option<&str>)
+                 -> Result<NicklistStore, ClientError> {
+        let request = try!(relay.nicklist(buffer));
+        let reply = try!(relay.wait(request));
+        let mut store = NicklistStore::new();
+        try!(store.load(&reply));
+        Ok(store)
+    }
+
+    /// Replace the nicklists of the buffers in `message`, a `_nicklist` event or the reply to
+    /// `nicklist`.
+    pub fn load(&mut self, message: &WeechatMessage) -> Result<(), WeechatParseError> {
+        let items = try!(NicklistItem::all_from(message));
+        self.replace(&items);
+        Ok(())
+    }
+
+    /// Keep up with `event`, returning whether it was about nicklists.
+    pub fn apply(&mut self, event: &RelayEvent) -> bool {
+        match *event {
+            RelayEvent::Nicklist(ref items) => self.replace(items),
+            RelayEvent::NicklistDiff(ref items) => self.apply_diff(items),
+            RelayEvent::BufferClosing(ref changes) => {
+                for change in changes {
+                    self.buffers.remove(&change.pointer);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn buffer(&self, pointer: &str) -> Option<&Nicklist> {
+        self.buffers.get(pointer)
+    }
+
+    fn replace(&mut self, items: &[NicklistItem]) {
+        for item in items {
+            self.buffers.remove(&item.buffer);
+        }
+        // Pointers and levels of the groups we are in, outermost first.
+        let mut path: Vec<(String, i32)> = vec![];
+        let mut buffer = None;
+        for item in items {
+            if buffer != Some(&item.buffer) {
+                buffer = Some(&item.buffer);
+                path.clear();
+            }
+            let nicklist = self.buffers.entry(item.buffer.clone()).or_insert_with(Nicklist::new);
+            if item.group {
+                while path.last().map(|&(_, level)| level >= item.level).unwrap_or(false) {
+                    path.pop();
+                }
+                nicklist.add(item, path.last().map(|&(ref pointer, _)| &pointer[..]));
+                path.push((item.pointer.clone(), item.level));
+            } else {
+                nicklist.add(item, path.last().map(|&(ref pointer, _)| &pointer[..]));
+            }
+        }
+    }
+
+    fn apply_diff(&mut self, items: &[NicklistItem]) {
+        let mut parent: Option<String> = None;
+        for item in items {
+            let nicklist = self.buffers.entry(item.buffer.clone()).or_insert_with(Nicklist::new);
+            match item.diff {
+                Some(NicklistDiff::Parent) => parent = Some(item.pointer.clone()),
+                Some(NicklistDiff::Added) => nicklist.add(item, parent.as_deref()),
+                Some(NicklistDiff::Removed) => nicklist.remove(&item.pointer),
+                Some(NicklistDiff::Changed) => nicklist.change(item),
+                None => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_group_display_name() {
+    let group = |name: &str| {
+        Group {
+            pointer: "0x1".to_owned(),
+            name: name.to_owned(),
+            color: "".to_owned(),
+            visible: true,
+            level: 1,
+            parent: None,
+        }
+    };
+    assert_eq!(group("000|o").display_name(), "o");
+    assert_eq!(group("root").display_name(), "root");
+    assert_eq!(group("a|b").display_name(), "a|b");
+}
@@ -0,0 +1,124 @@
+//! Matching replies to the commands that asked for them.
+//!
+//! Every command that gets a reply is sent with a `(id)` prefix the relay echoes back in the
+//! reply's id. Ids are allocated here and never start with `_`, which the relay keeps for the
+//! events it sends on its own.
+
+use std::collections::{HashMap, HashSet};
+use weechat_parser::WeechatMessage;
+
+/// A command waiting for its reply, hand it to `WeechatRelay::wait` to get the reply.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct Request {
+    id: String,
+}
+
+impl Request {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+pub struct IdAllocator {
+    next: u64,
+}
+
+impl IdAllocator {
+    pub fn new() -> IdAllocator {
+        IdAllocator { next: 1 }
+    }
+
+    pub fn allocate(&mut self) -> Request {
+        let id = format!("r{}", self.next);
+        self.next += 1;
+        Request { id: id }
+    }
+}
+
+/// Requests sent and not waited for yet, with their replies once they arrive.
+pub struct PendingRequests {
+    replies: HashMap<String, Option<WeechatMessage>>,
+    cancelled: HashSet<String>,
+}
+
+impl PendingRequests {
+    pub fn new() -> PendingRequests {
+        PendingRequests {
+            replies: HashMap::new(),
+            cancelled: HashSet::new(),
+        }
+    }
+
+    pub fn insert(&mut self, request: &Request) {
+        self.replies.insert(request.id.clone(), None);
+    }
+
+    /// Keep `message` if it is the reply to a pending or cancelled request, otherwise hand it
+    /// back.
+    pub fn resolve(&mut self, message: WeechatMessage) -> Option<WeechatMessage> {
+        if self.cancelled.remove(&message.id) {
+            return None;
+        }
+        match self.replies.get_mut(&message.id) {
+            Some(reply) if reply.is_none() => {
+                *reply = Some(message);
+                None
+            }
+            _ => Some(message),
+        }
+    }
+
+    /// Take the reply to `request` if it arrived.
+    pub fn take(&mut self, request: &Request) -> Option<WeechatMessage> {
+        let arrived = self.replies.get(&request.id).map(Option::is_some).unwrap_or(false);
+        if arrived {
+            self.replies.remove(&request.id).and_then(|reply| reply)
+        } else {
+            None
+        }
+    }
+
+    /// Forget `request`, dropping its reply whenever it arrives.
+    pub fn cancel(&mut self, request: &Request) {
+        if let Some(None) = self.replies.remove(&request.id) {
+            self.cancelled.insert(request.id.clone());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.replies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty()
+    }
+}
+
+#[test]
+fn test_pending_requests() {
+    let reply = |id: &str| {
+        WeechatMessage {
+            id: id.to_owned(),
+            data: vec![],
+        }
+    };
+    let mut ids = IdAllocator::new();
+    let (first, second, third) = (ids.allocate(), ids.allocate(), ids.allocate());
+    assert_eq!((first.id(), second.id()), ("r1", "r2"));
+
+    let mut pending = PendingRequests::new();
+    pending.insert(&first);
+    pending.insert(&second);
+    pending.insert(&third);
+    assert_eq!(pending.take(&first), None);
+    assert_eq!(pending.resolve(reply("r2")), None);
+    assert_eq!(pending.resolve(reply("_buffer_opened")), Some(reply("_buffer_opened")));
+    assert_eq!(pending.take(&second), Some(reply("r2")));
+    // A second reply with the same id is not ours to keep.
+    assert_eq!(pending.resolve(reply("r2")), Some(reply("r2")));
+
+    pending.cancel(&third);
+    assert_eq!(pending.resolve(reply("r3")), None);
+    assert_eq!(pending.resolve(reply("r3")), Some(reply("r3")));
+    assert_eq!(pending.len(), 1);
+}
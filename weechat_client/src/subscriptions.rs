@@ -0,0 +1,186 @@
+//! What we asked the relay to `sync`.
+//!
+//! The relay keeps a set of options for every buffer named in `sync`, and one for `*` covering
+//! all other buffers. A buffer with its own set only gets what that set says, whatever `*` has.
+//! `desync` takes options away from the set of the buffers it names, so it only quiets buffers
+//! that were synced by name.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// What `sync` sends for a buffer, see the relay protocol docs.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub enum SyncOption {
+    /// Lines and other changes to the buffer.
+    Buffer,
+    /// Changes to the nicklist.
+    Nicklist,
+    /// Buffers being opened, closed, renamed and the like, only for `*`.
+    Buffers,
+    /// WeeChat upgrading, only for `*`.
+    Upgrade,
+}
+
+/// Every option, what `sync *` without options means.
+pub const ALL_OPTIONS: [SyncOption; 4] = [SyncOption::Buffer,
+                                          SyncOption::Nicklist,
+                                          SyncOption::Buffers,
+                                          SyncOption::Upgrade];
+
+/// What `sync` of a named buffer without options means.
+pub const BUFFER_OPTIONS: [SyncOption; 2] = [SyncOption::Buffer, SyncOption::Nicklist];
+
+impl SyncOption {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SyncOption::Buffer => "buffer",
+            SyncOption::Nicklist => "nicklist",
+            SyncOption::Buffers => "buffers",
+            SyncOption::Upgrade => "upgrade",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SyncOption> {
+        ALL_OPTIONS.iter().cloned().find(|option| option.name() == name)
+    }
+}
+
+/// Options synced per buffer, by full name or pointer as they were given, `*` for all buffers.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Subscriptions {
+    buffers: BTreeMap<String, BTreeSet<SyncOption>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Subscriptions {
+        Subscriptions::default()
+    }
+
+    /// Record a `sync` of `buffers`, or of `*` if empty, and return the command to send.
+    pub fn sync(&mut self, buffers: &[&str], options: &[SyncOption]) -> String {
+        for buffer in names(buffers) {
+            let options = if options.is_empty() {
+                default_options(buffer)
+            } else {
+                options
+            };
+            self.buffers.entry(buffer.to_owned()).or_insert_with(BTreeSet::new).extend(options);
+        }
+        sync_command("sync", buffers, options)
+    }
+
+    /// Record a `desync` of `buffers`, or of `*` if empty, and return the command to send.
+    pub fn desync(&mut self, buffers: &[&str], options: &[SyncOption]) -> String {
+        for buffer in names(buffers) {
+            let emptied = match self.buffers.get_mut(buffer) {
+                Some(synced) if options.is_empty() => {
+                    synced.clear();
+                    true
+                }
+                Some(synced) => {
+                    for option in options {
+                        synced.remove(option);
+                    }
+                    synced.is_empty()
+                }
+                None => false,
+            };
+            if emptied {
+                self.buffers.remove(buffer);
+            }
+        }
+        sync_command("desync", buffers, options)
+    }
+
+    /// Whether the relay sends `option` for `buffer`, as long as it uses the name `buffer` was
+    /// synced with.
+    pub fn is_synced(&self, buffer: &str, option: SyncOption) -> bool {
+        match self.buffers.get(buffer).or_else(|| self.buffers.get("*")) {
+            Some(synced) => synced.contains(&option),
+            None => false,
+        }
+    }
+
+    /// Buffers synced by name, or `*`, with their options.
+    pub fn buffers(&self) -> Vec<(&str, Vec<SyncOption>)> {
+        self.buffers
+            .iter()
+            .map(|(buffer, options)| (&buffer[..], options.iter().cloned().collect()))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    /// The `sync` commands that get a new connection to the same state.
+    pub fn commands(&self) -> Vec<String> {
+        self.buffers
+            .iter()
+            .map(|(buffer, synced)| {
+                let options: Vec<SyncOption> = synced.iter().cloned().collect();
+                if &options[..] == default_options(buffer) {
+                    sync_command("sync", &[buffer], &[])
+                } else {
+                    sync_command("sync", &[buffer], &options)
+                }
+            })
+            .collect()
+    }
+}
+
+fn names<'a>(buffers: &[&'a str]) -> Vec<&'a str> {
+    if buffers.is_empty() {
+        vec!["*"]
+    } else {
+        buffers.to_vec()
+    }
+}
+
+fn default_options(buffer: &str) -> &'static [SyncOption] {
+    if buffer == "*" {
+        &ALL_OPTIONS
+    } else {
+        &BUFFER_OPTIONS
+    }
+}
+
+/// `sync` and `desync` take an optional comma separated list of buffers, then options.
+pub fn sync_command(name: &str, buffers: &[&str], options: &[SyncOption]) -> String {
+    let options: Vec<&str> = options.iter().map(SyncOption::name).collect();
+    match (buffers.is_empty(), options.is_empty()) {
+        (true, true) => format!("{}\n", name),
+        (false, true) => format!("{} {}\n", name, buffers.join(",")),
+        (true, false) => format!("{} * {}\n", name, options.join(",")),
+        (false, false) => format!("{} {} {}\n", name, buffers.join(","), options.join(",")),
+    }
+}
+
+#[test]
+fn test_sync_command() {
+    assert_eq!(sync_command("sync", &[], &[SyncOption::Buffers, SyncOption::Upgrade]),
+               "sync * buffers,upgrade\n");
+    assert_eq!(sync_command("sync", &["0x7fcab15936d0"], &[SyncOption::Nicklist]),
+               "sync 0x7fcab15936d0 nicklist\n");
+}
+
+#[test]
+fn test_subscriptions() {
+    let mut subscriptions = Subscriptions::new();
+    subscriptions.sync(&[], &[SyncOption::Buffers]);
+    subscriptions.sync(&["irc.libera.#weechat", "irc.libera.#rust"], &[]);
+    assert!(subscriptions.is_synced("irc.libera.#rust", SyncOption::Nicklist));
+    assert!(subscriptions.is_synced("core.weechat", SyncOption::Buffers));
+    assert!(!subscriptions.is_synced("core.weechat", SyncOption::Buffer));
+
+    subscriptions.desync(&["irc.libera.#rust"], &[SyncOption::Buffer]);
+    assert!(!subscriptions.is_synced("irc.libera.#rust", SyncOption::Buffer));
+    assert!(subscriptions.is_synced("irc.libera.#rust", SyncOption::Nicklist));
+    subscriptions.desync(&["irc.libera.#weechat"], &[]);
+    assert!(!subscriptions.is_synced("irc.libera.#weechat", SyncOption::Buffer));
+    assert_eq!(subscriptions.commands(),
+               vec!["sync * buffers\n", "sync irc.libera.#rust nicklist\n"]);
+
+    subscriptions.desync(&[], &[]);
+    subscriptions.desync(&["irc.libera.#rust"], &[]);
+    assert!(subscriptions.is_empty());
+}
@@ -0,0 +1,361 @@
+//! Keeping a connection to the relay up.
+//!
+//! A `Supervisor` connects as its `RelayConfig` says and, when the connection drops, connects
+//! and logs in again with exponential backoff, then syncs again what was synced before so events
+//! keep flowing as they did. Replies to requests sent on a dropped connection are
+//! lost. With a `Heartbeat` set, a connection that stops answering `ping` counts as dropped too.
+
+use std::cmp;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::Rng;
+use weechat_parser::WeechatMessage;
+use auth::AuthError;
+use keepalive::{Action, Heartbeat, Keepalive};
+use subscriptions::{Subscriptions, SyncOption};
+use {ClientError, RelayConfig, WeechatRelay, READ_TIMEOUT};
+
+/// What the supervisor is up to, sent to every receiver from `Supervisor::states`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ConnectionState {
+    /// Connecting and logging in, `attempt` counts from 1 since the last connection.
+    Connecting { attempt: u32 },
+    Connected,
+    /// The connection dropped, it is retried on the next `recv`.
+    Disconnected { reason: String },
+    /// `attempts` connections in a row failed and `Backoff::max_attempts` was reached, or
+    /// logging in failed in a way waiting won't fix.
+    GaveUp { attempts: u32 },
+}
+
+/// Rejected logins in a row that are retried once logging in worked before. The relay rejects a
+/// login by closing the connection, so a connection dropped while logging in looks the same, but
+/// a password changed on the relay must not be retried forever.
+const REJECTED_RETRIES: u32 = 3;
+
+/// How long to wait between connection attempts.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Backoff {
+    /// Wait after the first failed attempt.
+    pub initial: Duration,
+    /// The wait never grows past this.
+    pub max: Duration,
+    /// Every failed attempt multiplies the wait by this, taken as 1 if smaller.
+    pub multiplier: f64,
+    /// Fraction of the wait, from 0 to 1, that is randomly taken off so clients that lost the
+    /// relay together don't all come back at once.
+    pub jitter: f64,
+    /// Give up after this many failed attempts in a row, or never if `None`.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// The wait after `attempt` failed, without jitter. A multiplier below 1 counts as 1, so
+    /// delays never shrink from one attempt to the next.
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exponent = cmp::min(attempt.saturating_sub(1), 64) as i32;
+        let multiplier = if self.multiplier >= 1.0 { self.multiplier } else { 1.0 };
+        let seconds = self.initial.as_secs_f64() * multiplier.powi(exponent);
+        if !seconds.is_finite() || seconds >= self.max.as_secs_f64() {
+            self.max
+        } else {
+            Duration::from_secs_f64(seconds)
+        }
+    }
+
+    /// The wait after `attempt` failed, with jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        let jitter = self.jitter.max(0.0).min(1.0);
+        if jitter == 0.0 {
+            return base;
+        }
+        base.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=jitter))
+    }
+
+    fn gives_up_after(&self, attempt: u32) -> bool {
+        self.max_attempts.map(|max| attempt >= max).unwrap_or(false)
+    }
+}
+
+pub struct Supervisor {
+    config: RelayConfig,
+    backoff: Backoff,
+    relay: Option<WeechatRelay>,
+    heartbeat: Option<Heartbeat>,
+    /// Heartbeat state of the current connection.
+    keepalive: Option<Keepalive>,
+    /// Synced again after reconnecting.
+    subscriptions: Subscriptions,
+    listeners: Vec<Sender<ConnectionState>>,
+    /// Logging in with `config` worked before.
+    logged_in: bool,
+}
+
+impl Supervisor {
+    /// A supervisor for `config`, not connected until `connect` or `recv` is called.
+    pub fn new(config: RelayConfig, backoff: Backoff) -> Supervisor {
+        Supervisor {
+            config: config,
+            backoff: backoff,
+            relay: None,
+            heartbeat: None,
+            keepalive: None,
+            subscriptions: Subscriptions::new(),
+            listeners: vec![],
+            logged_in: false,
+        }
+    }
+
+    /// Receive every state change from now on.
+    pub fn states(&mut self) -> Receiver<ConnectionState> {
+        let (tx, rx) = channel();
+        self.listeners.push(tx);
+        rx
+    }
+
+    /// The current connection, if there is one.
+    pub fn relay(&mut self) -> Option<&mut WeechatRelay> {
+        self.relay.as_mut()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.relay.is_some()
+    }
+
+    /// Ping the relay as `heartbeat` says from the next connection on, reconnecting when it
+    /// stops answering.
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat = Some(heartbeat);
+    }
+
+    /// Round trip time of the last heartbeat on the current connection.
+    pub fn latency(&self) -> Option<Duration> {
+        self.keepalive.as_ref().and_then(Keepalive::latency)
+    }
+
+    /// Connect and log in if not connected yet, retrying as `Backoff` says. Authentication
+    /// errors are not retried, a password won't get any better by waiting, except for a few
+    /// rejected logins once logging in worked before, which more likely were cut short.
+    pub fn connect(&mut self) -> Result<&mut WeechatRelay, ClientError> {
+        if self.relay.is_none() {
+            let relay = try!(self.reconnect());
+            self.relay = Some(relay);
+            let now = Instant::now();
+            self.keepalive = self.heartbeat.map(|heartbeat| Keepalive::new(heartbeat, now));
+        }
+        Ok(self.relay.as_mut().unwrap())
+    }
+
+    /// Like `WeechatRelay::sync`, remembered so it is synced again after reconnecting.
+    pub fn sync(&mut self, buffers: &[&str]) -> Result<(), ClientError> {
+        self.sync_with(buffers, &[])
+    }
+
+    /// Like `WeechatRelay::sync_with`, remembered so it is synced again after reconnecting.
+    pub fn sync_with(&mut self,
+                     buffers: &[&str],
+                     options: &[SyncOption])
+                     -> Result<(), ClientError> {
+        let command = self.subscriptions.sync(buffers, options);
+        self.send_subscription(&command)
+    }
+
+    /// Like `WeechatRelay::desync`, remembered so it is not synced again after reconnecting.
+    pub fn desync(&mut self, buffers: &[&str]) -> Result<(), ClientError> {
+        self.desync_with(buffers, &[])
+    }
+
+    /// Like `WeechatRelay::desync_with`, remembered so it is not synced again after
+    /// reconnecting.
+    pub fn desync_with(&mut self,
+                       buffers: &[&str],
+                       options: &[SyncOption])
+                       -> Result<(), ClientError> {
+        let command = self.subscriptions.desync(buffers, options);
+        self.send_subscription(&command)
+    }
+
+    /// What is synced now, and again after reconnecting.
+    pub fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    /// Wait for the next message from the relay, reconnecting whenever the connection drops.
+    /// Fails once the supervisor gives up, or on errors that aren't about the connection.
+    pub fn recv(&mut self) -> Result<WeechatMessage, ClientError> {
+        loop {
+            if let Some(message) = try!(self.recv_timeout(READ_TIMEOUT)) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Like `recv`, giving up after `timeout` unless reconnecting takes longer.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<WeechatMessage>, ClientError> {
+        let started = Instant::now();
+        loop {
+            try!(self.connect());
+            let now = Instant::now();
+            let mut wait = (started + timeout).saturating_duration_since(now);
+            match self.keepalive.as_mut().map(|keepalive| keepalive.poll(now)) {
+                Some(Action::Dead) => {
+                    let timeout = self.heartbeat.unwrap().timeout;
+                    self.disconnected(format!("no _pong within {:?}", timeout));
+                    return Ok(None);
+                }
+                Some(Action::Ping(token)) => {
+                    let result = self.relay.as_mut().unwrap().ping(&token);
+                    try!(self.check_connection(result));
+                    continue;
+                }
+                Some(Action::Wait(until)) => wait = cmp::min(wait, until - now),
+                None => {}
+            }
+
+            let result = self.relay.as_mut().unwrap().recv_timeout(wait);
+            match try!(self.check_connection(result)) {
+                Some(Some(message)) => {
+                    let ours = match self.keepalive {
+                        Some(ref mut keepalive) => keepalive.received(&message, Instant::now()),
+                        None => false,
+                    };
+                    if !ours {
+                        return Ok(Some(message));
+                    }
+                }
+                Some(None) => {}
+                None => return Ok(None),
+            }
+            if started.elapsed() >= timeout {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn send_subscription(&mut self, command: &str) -> Result<(), ClientError> {
+        let result = match self.relay {
+            Some(ref mut relay) => relay.send(command),
+            None => return Ok(()),
+        };
+        // Sent again once we are back if the connection dropped.
+        try!(self.check_connection(result));
+        Ok(())
+    }
+
+    /// `None` if `result` says the connection dropped, which is then forgotten.
+    fn check_connection<T>(&mut self,
+                           result: Result<T, ClientError>)
+                           -> Result<Option<T>, ClientError> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(ref e) if is_connection_lost(e) => {
+                self.disconnected(e.to_string());
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Connect, log in and replay the subscriptions, retrying until it works or we give up.
+    fn reconnect(&mut self) -> Result<WeechatRelay, ClientError> {
+        let mut attempt = 1;
+        let mut rejections = 0;
+        loop {
+            self.notify(ConnectionState::Connecting { attempt: attempt });
+            match self.open() {
+                Ok(relay) => {
+                    self.logged_in = true;
+                    self.notify(ConnectionState::Connected);
+                    return Ok(relay);
+                }
+                Err(e) => {
+                    let retry = match e {
+                        ClientError::Auth(AuthError::Rejected) => {
+                            rejections += 1;
+                            self.logged_in && rejections <= REJECTED_RETRIES
+                        }
+                        ClientError::Auth(_) => false,
+                        _ => true,
+                    };
+                    if !retry || self.backoff.gives_up_after(attempt) {
+                        self.notify(ConnectionState::GaveUp { attempts: attempt });
+                        return Err(e);
+                    }
+                }
+            }
+            thread::sleep(self.backoff.delay(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn open(&self) -> Result<WeechatRelay, ClientError> {
+        let mut relay = try!(WeechatRelay::connect_with(&self.config));
+        for command in self.subscriptions.commands() {
+            try!(relay.send(&command));
+        }
+        Ok(relay)
+    }
+
+    fn disconnected(&mut self, reason: String) {
+        self.relay = None;
+        self.keepalive = None;
+        self.notify(ConnectionState::Disconnected { reason: reason });
+    }
+
+    fn notify(&mut self, state: ConnectionState) {
+        // Listeners that hung up are forgotten.
+        self.listeners.retain(|listener| listener.send(state.clone()).is_ok());
+    }
+}
+
+/// Errors after which the relay is worth connecting to again.
+fn is_connection_lost(error: &ClientError) -> bool {
+    match *error {
+        ClientError::Io(_) | ClientError::Closed => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn test_backoff_delay() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_attempts: Some(3),
+    };
+    assert_eq!(backoff.delay(1), Duration::from_millis(100));
+    assert_eq!(backoff.delay(3), Duration::from_millis(400));
+    assert_eq!(backoff.delay(5), Duration::from_secs(1));
+    assert_eq!(backoff.delay(1000), Duration::from_secs(1));
+    assert!(!backoff.gives_up_after(2));
+    assert!(backoff.gives_up_after(3));
+
+    let jittered = Backoff { jitter: 0.5, ..backoff };
+    for _ in 0..100 {
+        let delay = jittered.delay(2);
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+    }
+
+    for &multiplier in &[-2.0, 0.5, f64::NAN, f64::INFINITY] {
+        let odd = Backoff { multiplier: multiplier, ..backoff };
+        let delays: Vec<Duration> = (1..10).map(|attempt| odd.delay(attempt)).collect();
+        assert!(delays.windows(2).all(|pair| pair[0] <= pair[1]), "{}: {:?}", multiplier, delays);
+        assert!(delays[0] >= Duration::from_millis(100) && delays[8] <= Duration::from_secs(1));
+    }
+}
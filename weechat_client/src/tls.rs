@@ -0,0 +1,298 @@
+//! TLS for relays with `relay.network.tls_*` set up, built on rustls.
+//!
+//! Besides the usual verification against the system roots or a CA file, self-signed relay
+//! certificates can be pinned by their SHA-256 fingerprint, either given up front or recorded on
+//! the first connection ("trust on first use") in a known hosts style file of
+//! `host:port fingerprint` lines.
+
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rustls;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct,
+             RootCertStore, SignatureScheme, StreamOwned};
+use rustls_native_certs;
+use rustls_pemfile;
+use sha2::{Digest, Sha256};
+use auth::{from_hex, to_hex};
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// How long the TLS handshake may take before giving up.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum TlsVerification {
+    /// Verify the certificate chain against the operating system's roots.
+    SystemRoots,
+    /// Verify the certificate chain against the CA certificates in a PEM file.
+    CaFile(PathBuf),
+    /// Only accept a certificate with this SHA-256 fingerprint, whoever signed it.
+    Fingerprint(Vec<u8>),
+    /// Pin the fingerprint stored for the host and port in this file, storing it on the first
+    /// connection.
+    TrustOnFirstUse(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    Tls(rustls::Error),
+    /// The CA file or server name couldn't be used.
+    Config(String),
+    /// The certificate didn't match the pinned fingerprint.
+    FingerprintMismatch {
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            TlsError::Io(ref e) => write!(f, "io error during tls setup: {}", e),
+            TlsError::Tls(ref e) => write!(f, "tls error: {}", e),
+            TlsError::Config(ref detail) => write!(f, "bad tls configuration: {}", detail),
+            TlsError::FingerprintMismatch { ref expected, ref actual } => {
+                write!(f,
+                       "certificate fingerprint {} doesn't match pinned {}",
+                       fingerprint_string(actual),
+                       fingerprint_string(expected))
+            }
+        }
+    }
+}
+
+impl error::Error for TlsError {
+    fn description(&self) -> &str {
+        match *self {
+            TlsError::Io(_) => "io error during tls setup",
+            TlsError::Tls(_) => "tls error",
+            TlsError::Config(_) => "bad tls configuration",
+            TlsError::FingerprintMismatch { .. } => "certificate fingerprint mismatch",
+        }
+    }
+}
+
+impl From<io::Error> for TlsError {
+    fn from(error: io::Error) -> TlsError {
+        // rustls reports handshake failures through io::Error, dig the real error back out.
+        if error.get_ref().map(|inner| inner.is::<rustls::Error>()).unwrap_or(false) {
+            let inner = error.into_inner().unwrap().downcast::<rustls::Error>().unwrap();
+            return TlsError::Tls(*inner);
+        }
+        TlsError::Io(error)
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(error: rustls::Error) -> TlsError {
+        TlsError::Tls(error)
+    }
+}
+
+/// SHA-256 of a DER certificate, the same as `openssl x509 -fingerprint -sha256` shows.
+pub fn fingerprint(certificate: &[u8]) -> Vec<u8> {
+    Sha256::digest(certificate).to_vec()
+}
+
+/// Parse a fingerprint written as hex, with or without colons between the bytes.
+pub fn parse_fingerprint(value: &str) -> Option<Vec<u8>> {
+    from_hex(&value.replace(':', "")).and_then(|bytes| if bytes.len() == 32 {
+        Some(bytes)
+    } else {
+        None
+    })
+}
+
+fn fingerprint_string(fingerprint: &[u8]) -> String {
+    fingerprint.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(":")
+}
+
+/// Wrap `stream` in TLS and finish the handshake, verifying the relay as `verification` says.
+pub fn connect(stream: TcpStream,
+               server_name: &str,
+               verification: &TlsVerification)
+               -> Result<TlsStream, TlsError> {
+    let relay = known_relay(server_name, try!(stream.peer_addr()).port());
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = try!(ClientConfig::builder_with_provider(provider.clone())
+                           .with_safe_default_protocol_versions());
+    let pinned = match *verification {
+        TlsVerification::SystemRoots | TlsVerification::CaFile(_) => None,
+        TlsVerification::Fingerprint(ref expected) => Some(expected.clone()),
+        TlsVerification::TrustOnFirstUse(ref store) => try!(stored_fingerprint(store, &relay)),
+    };
+    let verifier = Arc::new(PinnedVerifier {
+        expected: pinned.clone(),
+        seen: Mutex::new(None),
+        provider: provider,
+    });
+    let config = match *verification {
+        TlsVerification::SystemRoots => {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        TlsVerification::CaFile(ref path) => {
+            let mut roots = RootCertStore::empty();
+            let mut reader = BufReader::new(try!(File::open(path)));
+            for certificate in rustls_pemfile::certs(&mut reader) {
+                try!(roots.add(try!(certificate)));
+            }
+            if roots.is_empty() {
+                return Err(TlsError::Config(format!("no certificates in {}", path.display())));
+            }
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        TlsVerification::Fingerprint(_) |
+        TlsVerification::TrustOnFirstUse(_) => {
+            builder.dangerous()
+                   .with_custom_certificate_verifier(verifier.clone())
+                   .with_no_client_auth()
+        }
+    };
+
+    let name = try!(ServerName::try_from(server_name.to_owned())
+                        .map_err(|_| TlsError::Config(format!("bad server name {:?}", server_name))));
+    let connection = try!(ClientConnection::new(Arc::new(config), name));
+    let previous_timeout = try!(stream.read_timeout());
+    try!(stream.set_read_timeout(Some(TLS_HANDSHAKE_TIMEOUT)));
+    let mut stream = StreamOwned::new(connection, stream);
+    while stream.conn.is_handshaking() {
+        if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+            let seen = verifier.seen.lock().unwrap().clone();
+            return match (pinned, seen) {
+                (Some(expected), Some(actual)) if expected != actual => {
+                    Err(TlsError::FingerprintMismatch {
+                        expected: expected,
+                        actual: actual,
+                    })
+                }
+                _ => Err(TlsError::from(e)),
+            };
+        }
+    }
+    try!(stream.sock.set_read_timeout(previous_timeout));
+
+    if let TlsVerification::TrustOnFirstUse(ref store) = *verification {
+        if verifier.expected.is_none() {
+            if let Some(actual) = verifier.seen.lock().unwrap().clone() {
+                try!(store_fingerprint(store, &relay, &actual));
+            }
+        }
+    }
+    Ok(stream)
+}
+
+/// How the relay at `host` and `port` is written in the trust on first use store. Relays on
+/// different ports of a host have their own certificates.
+fn known_relay(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn stored_fingerprint(store: &PathBuf, relay: &str) -> Result<Option<Vec<u8>>, TlsError> {
+    let file = match File::open(store) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(TlsError::Io(e)),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = try!(line);
+        let mut parts = line.split_whitespace();
+        if let (Some(stored_relay), Some(stored)) = (parts.next(), parts.next()) {
+            if stored_relay == relay {
+                return parse_fingerprint(stored).map(Some).ok_or(TlsError::Config(format!(
+                    "bad fingerprint for {} in {}",
+                    relay,
+                    store.display())));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn store_fingerprint(store: &PathBuf, relay: &str, fingerprint: &[u8]) -> io::Result<()> {
+    let mut file = try!(OpenOptions::new().create(true).append(true).open(store));
+    writeln!(file, "{} {}", relay, to_hex(fingerprint))
+}
+
+/// Accepts the certificate with the expected fingerprint, or any certificate if there is none
+/// yet, remembering what it saw. Handshake signatures are still checked against the certificate.
+#[derive(Debug)]
+struct PinnedVerifier {
+    expected: Option<Vec<u8>>,
+    seen: Mutex<Option<Vec<u8>>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(&self,
+                          end_entity: &CertificateDer,
+                          _intermediates: &[CertificateDer],
+                          _server_name: &ServerName,
+                          _ocsp_response: &[u8],
+                          _now: UnixTime)
+                          -> Result<ServerCertVerified, rustls::Error> {
+        let actual = fingerprint(end_entity);
+        *self.seen.lock().unwrap() = Some(actual.clone());
+        match self.expected {
+            Some(ref expected) if *expected != actual => {
+                Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+            }
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &CertificateDer,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message,
+                                               cert,
+                                               dss,
+                                               &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &CertificateDer,
+                              dss: &DigitallySignedStruct)
+                              -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message,
+                                               cert,
+                                               dss,
+                                               &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[test]
+fn test_parse_fingerprint() {
+    let colons = "AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:\
+                  AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89";
+    let fingerprint = parse_fingerprint(colons).unwrap();
+    assert_eq!(fingerprint.len(), 32);
+    assert_eq!(fingerprint_string(&fingerprint), colons);
+    assert_eq!(parse_fingerprint(&to_hex(&fingerprint)), Some(fingerprint));
+    assert_eq!(parse_fingerprint("AB:CD"), None);
+}
@@ -0,0 +1,263 @@
+//! What `WeechatRelay` talks to the relay through.
+//!
+//! Anything that can carry bytes both ways can be a transport: the TCP, TLS and WebSocket
+//! connections `WeechatRelay` makes itself, Unix domain sockets (`/relay add unix.weechat ...` or
+//! an `ssh -L` forward), the stdio of a child process like `ssh host nc localhost 9000`, or an
+//! in-memory pair for tests.
+
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use tls::TlsStream;
+use websocket::WebSocketStream;
+
+pub trait Transport: Send {
+    /// Read whatever bytes the relay sent next into `buf`, returning how many. `Ok(0)` means the
+    /// relay closed the connection. Transports should give up after a short while with a
+    /// `WouldBlock` or `TimedOut` error so the client can check its own timeouts; ones that
+    /// block forever still work, their timeouts just won't fire.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Send a whole command line, newline included.
+    fn write_command(&mut self, command: &str) -> io::Result<()>;
+
+    fn close(&mut self) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        self.write_all(command.as_bytes())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        self.write_all(command.as_bytes())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Transport for TlsStream {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        try!(self.write_all(command.as_bytes()));
+        self.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        try!(self.flush());
+        self.sock.shutdown(Shutdown::Both)
+    }
+}
+
+impl<S: Read + Write + Send> Transport for WebSocketStream<S> {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        try!(self.write_all(command.as_bytes()));
+        self.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+}
+
+/// Any `Read + Write` stream, for proxies and tunnels the client doesn't know about.
+pub struct StreamTransport<S> {
+    stream: S,
+}
+
+impl<S: Read + Write + Send> StreamTransport<S> {
+    pub fn new(stream: S) -> StreamTransport<S> {
+        StreamTransport { stream: stream }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write + Send> Transport for StreamTransport<S> {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        try!(self.stream.write_all(command.as_bytes()));
+        self.stream.flush()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A child process whose stdout is the relay's output and stdin its input, like
+/// `ssh host nc localhost 9000`.
+pub struct CommandTransport {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+}
+
+impl CommandTransport {
+    pub fn spawn(command: &mut Command) -> io::Result<CommandTransport> {
+        let mut child = try!(command.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn());
+        let stdin = child.stdin.take();
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "child has no stdout")),
+        };
+        Ok(CommandTransport {
+            child: child,
+            stdin: stdin,
+            stdout: stdout,
+        })
+    }
+}
+
+impl Transport for CommandTransport {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        match self.stdin {
+            Some(ref mut stdin) => {
+                try!(stdin.write_all(command.as_bytes()));
+                stdin.flush()
+            }
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "transport closed")),
+        }
+    }
+
+    /// Close the child's stdin and wait for it to exit.
+    fn close(&mut self) -> io::Result<()> {
+        self.stdin.take();
+        self.child.wait().map(|_| ())
+    }
+}
+
+impl Drop for CommandTransport {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// One end of an in-memory connection, see `memory_pair`.
+pub struct MemoryTransport {
+    tx: Option<Sender<Vec<u8>>>,
+    rx: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    read_timeout: Duration,
+}
+
+/// Two connected in-memory transports, whatever is written to one is read from the other. Reads
+/// time out after a second like the sockets `WeechatRelay` opens.
+pub fn memory_pair() -> (MemoryTransport, MemoryTransport) {
+    let (tx_a, rx_a) = channel();
+    let (tx_b, rx_b) = channel();
+    let end = |tx, rx| {
+        MemoryTransport {
+            tx: Some(tx),
+            rx: rx,
+            pending: vec![],
+            read_timeout: Duration::from_millis(1000),
+        }
+    };
+    (end(tx_a, rx_b), end(tx_b, rx_a))
+}
+
+impl MemoryTransport {
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    /// Send raw bytes to the other end, like an encoded relay message.
+    pub fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.tx {
+            Some(ref tx) => {
+                tx.send(data.to_vec())
+                  .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "other end closed"))
+            }
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "transport closed")),
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv_timeout(self.read_timeout) {
+                Ok(data) => self.pending = data,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, "no data yet"))
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let count = buf.len().min(self.pending.len());
+        buf[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+
+    fn write_command(&mut self, command: &str) -> io::Result<()> {
+        self.write_bytes(command.as_bytes())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.tx.take();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_memory_pair() {
+    let (mut client, mut relay) = memory_pair();
+    client.write_command("init\n").unwrap();
+    let mut buffer = [0; 3];
+    assert_eq!(relay.read_bytes(&mut buffer).unwrap(), 3);
+    assert_eq!(&buffer, b"ini");
+    assert_eq!(relay.read_bytes(&mut buffer).unwrap(), 2);
+    assert_eq!(&buffer[..2], b"t\n");
+
+    relay.set_read_timeout(Duration::from_millis(10));
+    assert_eq!(relay.read_bytes(&mut buffer).unwrap_err().kind(),
+               io::ErrorKind::WouldBlock);
+    client.close().unwrap();
+    assert_eq!(relay.read_bytes(&mut buffer).unwrap(), 0);
+}
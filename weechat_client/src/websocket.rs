@@ -0,0 +1,222 @@
+//! The relay protocol over WebSocket, as Glowing Bear speaks it.
+//!
+//! Commands go out as one text frame per line and the relay answers with binary frames holding
+//! the usual length prefixed messages, so the bytes read here go through the same framing and
+//! parsing as a plain TCP connection.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+use tungstenite;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::HandshakeError;
+use tungstenite::{Message, WebSocket};
+#[cfg(feature = "tls")]
+use tls::TlsError;
+
+/// How long the HTTP upgrade may take before giving up.
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    WebSocket(tungstenite::Error),
+    /// The URL isn't a `ws://` or `wss://` URL we can connect to.
+    Url(String),
+    #[cfg(feature = "tls")]
+    Tls(TlsError),
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            WebSocketError::Io(ref e) => write!(f, "io error during websocket setup: {}", e),
+            WebSocketError::WebSocket(ref e) => write!(f, "websocket error: {}", e),
+            WebSocketError::Url(ref detail) => write!(f, "bad websocket url: {}", detail),
+            #[cfg(feature = "tls")]
+            WebSocketError::Tls(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for WebSocketError {
+    fn description(&self) -> &str {
+        match *self {
+            WebSocketError::Io(_) => "io error during websocket setup",
+            WebSocketError::WebSocket(_) => "websocket error",
+            WebSocketError::Url(_) => "bad websocket url",
+            #[cfg(feature = "tls")]
+            WebSocketError::Tls(_) => "tls error",
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(error: io::Error) -> WebSocketError {
+        WebSocketError::Io(error)
+    }
+}
+
+impl From<tungstenite::Error> for WebSocketError {
+    fn from(error: tungstenite::Error) -> WebSocketError {
+        WebSocketError::WebSocket(error)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<TlsError> for WebSocketError {
+    fn from(error: TlsError) -> WebSocketError {
+        WebSocketError::Tls(error)
+    }
+}
+
+/// Where a WebSocket URL points.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub secure: bool,
+}
+
+impl Endpoint {
+    pub fn from_url(url: &str) -> Result<Endpoint, WebSocketError> {
+        let request = try!(url.into_client_request());
+        let uri = request.uri();
+        let secure = match uri.scheme_str() {
+            Some("ws") => false,
+            Some("wss") => true,
+            other => return Err(WebSocketError::Url(format!("unsupported scheme {:?}", other))),
+        };
+        let host = match uri.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+            None => return Err(WebSocketError::Url(format!("no host in {:?}", url))),
+        };
+        Ok(Endpoint {
+            host: host,
+            port: uri.port_u16().unwrap_or(if secure { 443 } else { 80 }),
+            secure: secure,
+        })
+    }
+}
+
+/// A WebSocket connection that reads and writes like a byte stream.
+pub struct WebSocketStream<S: Read + Write> {
+    socket: WebSocket<S>,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl<S: Read + Write> WebSocketStream<S> {
+    pub fn get_ref(&self) -> &S {
+        self.socket.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        self.socket.get_mut()
+    }
+
+    /// Send a close frame, leaving the other end to close the connection.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        let result = self.socket.close(None);
+        match result.and_then(|_| self.socket.flush()) {
+            Ok(()) |
+            Err(tungstenite::Error::ConnectionClosed) |
+            Err(tungstenite::Error::AlreadyClosed) => Ok(()),
+            Err(e) => Err(to_io_error(e)),
+        }
+    }
+}
+
+/// Do the HTTP upgrade for `url` over an already connected `stream`, retrying through read
+/// timeouts until `UPGRADE_TIMEOUT`.
+pub fn upgrade<S: Read + Write>(url: &str, stream: S) -> Result<WebSocketStream<S>, WebSocketError> {
+    let started = Instant::now();
+    let mut result = tungstenite::client(url, stream);
+    loop {
+        match result {
+            Ok((socket, _)) => {
+                return Ok(WebSocketStream {
+                    socket: socket,
+                    incoming: vec![],
+                    outgoing: vec![],
+                })
+            }
+            Err(HandshakeError::Interrupted(_)) if started.elapsed() > UPGRADE_TIMEOUT => {
+                return Err(WebSocketError::Io(io::Error::new(io::ErrorKind::TimedOut,
+                                                             "websocket upgrade timed out")))
+            }
+            Err(HandshakeError::Interrupted(handshake)) => result = handshake.handshake(),
+            Err(HandshakeError::Failure(e)) => return Err(WebSocketError::WebSocket(e)),
+        }
+    }
+}
+
+fn to_io_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed |
+        tungstenite::Error::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "websocket closed")
+        }
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+impl<S: Read + Write> Read for WebSocketStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.incoming.is_empty() {
+            match self.socket.read() {
+                Ok(Message::Binary(data)) => self.incoming.extend_from_slice(&data),
+                Ok(Message::Text(text)) => self.incoming.extend_from_slice(text.as_bytes()),
+                Ok(Message::Close(_)) |
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(0),
+                // Pings are answered by tungstenite on the next write.
+                Ok(_) => {}
+                Err(e) => return Err(to_io_error(e)),
+            }
+        }
+        let count = buf.len().min(self.incoming.len());
+        buf[..count].copy_from_slice(&self.incoming[..count]);
+        self.incoming.drain(..count);
+        Ok(count)
+    }
+}
+
+impl<S: Read + Write> Write for WebSocketStream<S> {
+    /// Commands are buffered until their newline, then sent as a text frame each.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend_from_slice(buf);
+        while let Some(end) = self.outgoing.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.outgoing.drain(..end + 1).collect();
+            let text = String::from_utf8_lossy(&line).into_owned();
+            try!(self.socket.write(Message::text(text)).map_err(to_io_error));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush().map_err(to_io_error)
+    }
+}
+
+#[test]
+fn test_endpoint_from_url() {
+    assert_eq!(Endpoint::from_url("ws://localhost:9001/weechat").unwrap(),
+               Endpoint {
+                   host: "localhost".to_owned(),
+                   port: 9001,
+                   secure: false,
+               });
+    assert_eq!(Endpoint::from_url("wss://relay.example.com/weechat").unwrap(),
+               Endpoint {
+                   host: "relay.example.com".to_owned(),
+                   port: 443,
+                   secure: true,
+               });
+    match Endpoint::from_url("http://localhost/weechat") {
+        Err(_) => {}
+        other => panic!("expected an error, got {:?}", other),
+    }
+}
@@ -0,0 +1,107 @@
+extern crate weechat_client;
+
+use std::time::Duration;
+use weechat_client::{ClientError, RelayConfig, WeechatRelay};
+use weechat_client::auth::{AuthError, HashAlgorithm, Totp};
+use weechat_client::mock::MockRelay;
+
+fn relay_with_password(password: &str, algorithms: Vec<HashAlgorithm>) -> MockRelay {
+    let relay = MockRelay::start().unwrap();
+    relay.require_password(password);
+    relay.set_hash_algorithms(algorithms);
+    relay
+}
+
+fn totp_config(relay: &MockRelay, totp: Totp) -> RelayConfig {
+    let mut config = RelayConfig::tcp(&relay.address().to_string(), "secret");
+    config.totp = Some(totp);
+    config
+}
+
+fn init_line(relay: &MockRelay) -> String {
+    relay.wait_for_command("init", Duration::from_secs(5)).unwrap();
+    relay.commands().into_iter().find(|command| command.starts_with("init ")).unwrap()
+}
+
+#[test]
+fn logs_in_with_each_algorithm() {
+    for &algorithm in &[HashAlgorithm::Sha256,
+                        HashAlgorithm::Sha512,
+                        HashAlgorithm::Pbkdf2Sha256,
+                        HashAlgorithm::Pbkdf2Sha512] {
+        let relay = relay_with_password("secret", vec![algorithm]);
+        let mut client = WeechatRelay::connect(relay.address()).unwrap();
+        client.login("secret").unwrap();
+        let expected = format!("init password_hash={}:", algorithm.name());
+        assert!(init_line(&relay).starts_with(&expected));
+    }
+}
+
+#[test]
+fn picks_strongest_common_algorithm() {
+    let relay = relay_with_password("secret", vec![HashAlgorithm::Sha256, HashAlgorithm::Sha512]);
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    client.login("secret").unwrap();
+    assert!(init_line(&relay).starts_with("init password_hash=sha512:"));
+}
+
+#[test]
+fn logs_in_with_plain_password() {
+    let relay = relay_with_password("pass,word", vec![HashAlgorithm::Plain]);
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    client.login("pass,word").unwrap();
+    assert_eq!(init_line(&relay), "init password=pass\\,word");
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let relay = relay_with_password("secret", vec![HashAlgorithm::Pbkdf2Sha512]);
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    match client.login("hunter2") {
+        Err(ClientError::Auth(AuthError::Rejected)) => {}
+        other => panic!("expected Rejected, got {:?}", other),
+    }
+}
+
+#[test]
+fn no_common_algorithm_is_unsupported() {
+    let relay = relay_with_password("secret", vec![HashAlgorithm::Pbkdf2Sha512]);
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    match client.login_with("secret", None, &[HashAlgorithm::Sha256]) {
+        Err(ClientError::Auth(AuthError::UnsupportedAlgorithm(_))) => {}
+        other => panic!("expected UnsupportedAlgorithm, got {:?}", other),
+    }
+}
+
+#[test]
+fn logs_in_with_totp_secret() {
+    let relay = relay_with_password("secret", vec![HashAlgorithm::Sha512]);
+    relay.require_totp(b"12345678901234567890");
+    let totp = Totp::from_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+    WeechatRelay::connect_with(&totp_config(&relay, totp)).unwrap();
+    assert_eq!(relay.commands().iter().filter(|c| c.starts_with("init ")).count(), 1);
+    assert!(init_line(&relay).contains(",totp="));
+}
+
+#[test]
+fn static_totp_code_is_not_retried() {
+    let relay = relay_with_password("secret", vec![HashAlgorithm::Sha512]);
+    relay.require_totp(b"12345678901234567890");
+    let totp = Totp::Code("000000".to_owned());
+    match WeechatRelay::connect_with(&totp_config(&relay, totp)) {
+        Err(ClientError::Auth(AuthError::Rejected)) => {}
+        other => panic!("expected Rejected, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(relay.commands().iter().filter(|c| c.starts_with("init ")).count(), 1);
+}
+
+#[test]
+fn missing_totp_is_reported() {
+    let relay = relay_with_password("secret", vec![HashAlgorithm::Sha512]);
+    relay.require_totp(b"12345678901234567890");
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    match client.login("secret") {
+        Err(ClientError::Auth(AuthError::TotpRequired)) => {}
+        other => panic!("expected TotpRequired, got {:?}", other),
+    }
+}
@@ -0,0 +1,51 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::collections::HashMap;
+use std::time::Duration;
+use weechat_client::buffers::BUFFERS_HDATA;
+use weechat_client::mock::MockRelay;
+use weechat_client::{BufferStore, WeechatRelay};
+use weechat_parser::events::RelayEvent;
+use weechat_parser::{WeechatData, WeechatMessage};
+
+fn buffers_message(id: &str, buffers: Vec<(&str, i32, &str)>) -> WeechatMessage {
+    let pointers = buffers.iter().map(|&(pointer, _, _)| WeechatData::Pointer(pointer.to_owned()));
+    let rows = buffers.iter().map(|&(_, number, full_name)| {
+        let mut row = HashMap::new();
+        row.insert("number".to_owned(), WeechatData::Int(number));
+        row.insert("full_name".to_owned(), WeechatData::String(full_name.to_owned()));
+        row.insert("short_name".to_owned(),
+                   WeechatData::String(full_name.rsplit('.').next().unwrap().to_owned()));
+        row
+    });
+    WeechatMessage {
+        id: id.to_owned(),
+        data: vec![WeechatData::Hdata("buffer".to_owned(), pointers.collect(), rows.collect())],
+    }
+}
+
+#[test]
+fn follows_buffers_from_the_relay() {
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("hdata",
+                     vec![buffers_message("",
+                                          vec![("0x1", 1, "core.weechat"),
+                                               ("0x7fcab15936d0", 2, "irc.libera.#weechat")])]);
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    client.login("").unwrap();
+    let mut store = BufferStore::fetch(&mut client).unwrap();
+    let hdata = relay.wait_for_command("hdata", Duration::from_secs(5)).unwrap();
+    assert_eq!(hdata.arguments, BUFFERS_HDATA);
+    assert_eq!(store.by_pointer("0x7fcab15936d0").unwrap().short_name, "#weechat");
+    assert_eq!(store.by_number(1)[0].full_name, "core.weechat");
+
+    relay.send(&buffers_message("_buffer_opened", vec![("0x3", 3, "irc.libera.#rust")])).unwrap();
+    relay.send(&buffers_message("_buffer_closing", vec![("0x1", 1, "core.weechat")])).unwrap();
+    for _ in 0..2 {
+        let event = RelayEvent::from_message(client.recv().unwrap()).unwrap();
+        assert!(store.apply(&event));
+    }
+    let names: Vec<&str> = store.buffers().iter().map(|buffer| &buffer.full_name[..]).collect();
+    assert_eq!(names, vec!["irc.libera.#weechat", "irc.libera.#rust"]);
+}
@@ -0,0 +1,98 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::Duration;
+use weechat_client::capture::{Capture, Direction, Recorder, Speed};
+use weechat_client::mock::{line_added, MockRelay};
+use weechat_parser::WeechatMessage;
+
+fn read_messages<R: Read>(stream: &mut R, count: usize) -> Vec<WeechatMessage> {
+    let (tx, rx) = weechat_parser::new();
+    let mut messages = vec![];
+    let mut buffer = [0; 16];
+    while messages.len() < count {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0, "relay closed the connection early");
+        tx.send(buffer[..read].to_vec()).unwrap();
+        while let Ok(message) = rx.recv_timeout(Duration::from_millis(10)) {
+            messages.push(message.unwrap());
+        }
+    }
+    messages
+}
+
+fn record_session(relay: &MockRelay) -> Capture {
+    let (recorder, path) = Recorder::create_in(env::temp_dir()).unwrap();
+    {
+        let stream = TcpStream::connect(relay.address()).unwrap();
+        let mut out_stream = recorder.tee(stream.try_clone().unwrap());
+        let mut in_stream = recorder.tee(stream);
+        out_stream.write_all(b"init\nsync\n").unwrap();
+        let messages = read_messages(&mut in_stream, 2);
+        assert_eq!(messages, vec![line_added("one"), line_added("two")]);
+    }
+    let capture = Capture::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    capture
+}
+
+#[test]
+fn records_both_directions() {
+    let relay = MockRelay::start().unwrap();
+    relay.on_sync(vec![line_added("one"), line_added("two")]);
+    let capture = record_session(&relay);
+
+    let sent: Vec<u8> = capture.chunks
+                               .iter()
+                               .filter(|chunk| chunk.direction == Direction::Sent)
+                               .flat_map(|chunk| chunk.data.clone())
+                               .collect();
+    assert_eq!(sent, b"init\nsync\n".to_vec());
+    // Reads were done 16 bytes at a time, so the boundaries should be preserved.
+    assert!(capture.received().count() > 2);
+    assert!(capture.received().all(|chunk| chunk.data.len() <= 16));
+}
+
+#[test]
+fn replays_capture_through_parser() {
+    let relay = MockRelay::start().unwrap();
+    relay.on_sync(vec![line_added("one"), line_added("two")]);
+    let capture = record_session(&relay);
+
+    let rx = capture.replay(Speed::Accelerated(100));
+    assert_eq!(rx.recv().unwrap().unwrap(), line_added("one"));
+    assert_eq!(rx.recv().unwrap().unwrap(), line_added("two"));
+    assert!(rx.recv().is_err());
+}
+
+#[test]
+fn replays_capture_into_mock_relay() {
+    let relay = MockRelay::start().unwrap();
+    relay.on_sync(vec![line_added("one"), line_added("two")]);
+    let capture = record_session(&relay);
+
+    let replay_relay = MockRelay::start().unwrap();
+    let mut stream = TcpStream::connect(replay_relay.address()).unwrap();
+    stream.write_all(b"init\n").unwrap();
+    replay_relay.wait_for_command("init", Duration::from_secs(5)).unwrap();
+    replay_relay.replay(&capture, Speed::Instant).unwrap();
+    assert_eq!(read_messages(&mut stream, 2), vec![line_added("one"), line_added("two")]);
+}
+
+#[test]
+fn replays_raw_fixture() {
+    let mut f = File::open("../weechat_parser/tests/fodder/simple.dat").unwrap();
+    let mut buffer = vec![];
+    f.read_to_end(&mut buffer).unwrap();
+
+    let rx = Capture::from_raw(buffer).replay(Speed::Original);
+    for _ in 0..5 {
+        assert_eq!(rx.recv().unwrap().unwrap().id, "_buffer_line_added");
+    }
+    assert!(rx.recv().is_err());
+}
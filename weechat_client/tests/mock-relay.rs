@@ -0,0 +1,109 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+use weechat_client::mock::{line_added, MockRelay};
+use weechat_parser::{Compression, WeechatData, WeechatMessage};
+use weechat_parser::errors::WeechatParseError;
+
+fn connect(relay: &MockRelay) -> (TcpStream, Receiver<Result<WeechatMessage, WeechatParseError>>) {
+    let stream = TcpStream::connect(relay.address()).unwrap();
+    let mut in_stream = stream.try_clone().unwrap();
+    let (tx, rx) = weechat_parser::new();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        loop {
+            match in_stream.read(&mut buffer) {
+                Ok(0) | Err(_) => return,
+                Ok(count) => {
+                    if tx.send(buffer[..count].to_vec()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    (stream, rx)
+}
+
+fn recv(rx: &Receiver<Result<WeechatMessage, WeechatParseError>>) -> WeechatMessage {
+    rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap()
+}
+
+#[test]
+fn records_commands() {
+    let relay = MockRelay::start().unwrap();
+    let (mut stream, _rx) = connect(&relay);
+    stream.write_all(b"init password=secret\nsync\n").unwrap();
+    relay.wait_for_command("sync", Duration::from_secs(5)).unwrap();
+    assert_eq!(relay.commands(), vec!["init password=secret", "sync"]);
+}
+
+#[test]
+fn replays_scripted_replies_with_ids() {
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("hdata",
+                     vec![WeechatMessage {
+                              id: "".to_owned(),
+                              data: vec![WeechatData::Int(42)],
+                          }]);
+    let (mut stream, rx) = connect(&relay);
+    stream.write_all(b"init\n(listbuffers) hdata buffer:gui_buffers(*) number\n").unwrap();
+    let message = recv(&rx);
+    assert_eq!(message.id, "listbuffers");
+    assert_eq!(message.data, vec![WeechatData::Int(42)]);
+}
+
+#[test]
+fn sends_sync_messages_and_pushed_events() {
+    let relay = MockRelay::start().unwrap();
+    relay.set_compression(Compression::Zlib);
+    relay.on_sync(vec![line_added("first")]);
+    let (mut stream, rx) = connect(&relay);
+    stream.write_all(b"init\nsync\n").unwrap();
+    assert_eq!(recv(&rx), line_added("first"));
+
+    relay.send(&line_added("second")).unwrap();
+    assert_eq!(recv(&rx), line_added("second"));
+}
+
+#[test]
+fn answers_ping_with_pong() {
+    let relay = MockRelay::start().unwrap();
+    let (mut stream, rx) = connect(&relay);
+    stream.write_all(b"init\nping 1234\n").unwrap();
+    let message = recv(&rx);
+    assert_eq!(message.id, "_pong");
+    assert_eq!(message.data, vec![WeechatData::String("1234".to_owned())]);
+}
+
+#[test]
+fn quit_closes_connection() {
+    let relay = MockRelay::start().unwrap();
+    let (mut stream, rx) = connect(&relay);
+    stream.write_all(b"init\nquit\n").unwrap();
+    relay.wait_for_command("quit", Duration::from_secs(5)).unwrap();
+    assert!(rx.recv_timeout(Duration::from_secs(5)).is_err());
+}
+
+#[test]
+fn sends_past_clients_that_went_away() {
+    let relay = MockRelay::start().unwrap();
+    let (mut gone, _gone_rx) = connect(&relay);
+    gone.write_all(b"init\n").unwrap();
+    relay.wait_for_command("init", Duration::from_secs(5)).unwrap();
+    let (mut stream, rx) = connect(&relay);
+    stream.write_all(b"init\nping\n").unwrap();
+    relay.wait_for_command("ping", Duration::from_secs(5)).unwrap();
+    recv(&rx);
+    drop(gone);
+
+    for message in &["first", "second", "third"] {
+        relay.send(&line_added(message)).unwrap();
+        assert_eq!(recv(&rx), line_added(message));
+    }
+}
@@ -0,0 +1,134 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::collections::HashMap;
+use weechat_client::mock::MockRelay;
+use weechat_client::{NicklistStore, WeechatRelay};
+use weechat_parser::events::RelayEvent;
+use weechat_parser::{Compression, WeechatData, WeechatMessage};
+
+const BUFFER: &'static str = "0x7fcab15936d0";
+
+/// A nick or group row, `diff` is only sent in `_nicklist_diff`.
+struct Item {
+    diff: Option<char>,
+    pointer: &'static str,
+    group: bool,
+    level: i32,
+    name: &'static str,
+    prefix: &'static str,
+}
+
+fn group(diff: Option<char>, pointer: &'static str, level: i32, name: &'static str) -> Item {
+    Item {
+        diff: diff,
+        pointer: pointer,
+        group: true,
+        level: level,
+        name: name,
+        prefix: "",
+    }
+}
+
+fn nick(diff: Option<char>,
+        pointer: &'static str,
+        name: &'static str,
+        prefix: &'static str)
+        -> Item {
+    Item {
+        diff: diff,
+        pointer: pointer,
+        group: false,
+        level: 0,
+        name: name,
+        prefix: prefix,
+    }
+}
+
+/// `items` as the relay would send them, through the wire encoding and back.
+fn encoded(id: &str, items: Vec<Item>) -> WeechatMessage {
+    let mut pointers = vec![];
+    let mut rows = vec![];
+    for item in items {
+        pointers.push(WeechatData::Pointer(BUFFER.to_owned()));
+        pointers.push(WeechatData::Pointer(item.pointer.to_owned()));
+        let mut row = HashMap::new();
+        if let Some(diff) = item.diff {
+            row.insert("_diff".to_owned(), WeechatData::Char(diff));
+        }
+        let flag = if item.group { '\u{1}' } else { '\u{0}' };
+        row.insert("group".to_owned(), WeechatData::Char(flag));
+        row.insert("visible".to_owned(), WeechatData::Char('\u{1}'));
+        row.insert("level".to_owned(), WeechatData::Int(item.level));
+        row.insert("name".to_owned(), WeechatData::String(item.name.to_owned()));
+        row.insert("color".to_owned(), WeechatData::String("default".to_owned()));
+        row.insert("prefix".to_owned(), WeechatData::String(item.prefix.to_owned()));
+        row.insert("prefix_color".to_owned(), WeechatData::String("lightgreen".to_owned()));
+        rows.push(row);
+    }
+    let message = WeechatMessage {
+        id: id.to_owned(),
+        data: vec![WeechatData::Hdata("buffer/nicklist_item".to_owned(), pointers, rows)],
+    };
+    let bytes = message.to_bytes(Compression::Zlib).unwrap();
+    WeechatMessage::from_raw_message(&bytes).unwrap()
+}
+
+fn channel() -> Vec<Item> {
+    vec![group(None, "0x10", 0, "root"),
+         group(None, "0x11", 1, "000|o"),
+         nick(None, "0x21", "Wraithan", "@"),
+         group(None, "0x12", 1, "999|..."),
+         nick(None, "0x22", "mythmon", " "),
+         nick(None, "0x23", "FlashCode", " ")]
+}
+
+fn names(store: &NicklistStore) -> Vec<&str> {
+    store.buffer(BUFFER).unwrap().nicks().iter().map(|nick| &nick.name[..]).collect()
+}
+
+#[test]
+fn loads_full_nicklist() {
+    let mut store = NicklistStore::new();
+    assert!(store.apply(&RelayEvent::from_message(encoded("_nicklist", channel())).unwrap()));
+    let nicklist = store.buffer(BUFFER).unwrap();
+    let op = nicklist.nick("Wraithan").unwrap();
+    assert_eq!(op.prefix, "@");
+    assert_eq!(nicklist.group_of(op).unwrap().display_name(), "o");
+    assert_eq!(nicklist.group("0x12").unwrap().parent, Some("0x10".to_owned()));
+    assert_eq!(nicklist.group("0x10").unwrap().parent, None);
+    assert_eq!(nicklist.nicks_in("0x12").len(), 2);
+}
+
+#[test]
+fn follows_nicklist_diffs() {
+    let mut store = NicklistStore::new();
+    store.load(&encoded("_nicklist", channel())).unwrap();
+    let diff = encoded("_nicklist_diff",
+                       vec![group(Some('^'), "0x12", 1, "999|..."),
+                            nick(Some('+'), "0x24", "emberian", " "),
+                            nick(Some('-'), "0x23", "FlashCode", " "),
+                            nick(Some('*'), "0x22", "mythmon_", " ")]);
+    assert!(store.apply(&RelayEvent::from_message(diff).unwrap()));
+    assert_eq!(names(&store), vec!["Wraithan", "mythmon_", "emberian"]);
+    let nicklist = store.buffer(BUFFER).unwrap();
+    assert_eq!(nicklist.nick("emberian").unwrap().group, "0x12");
+
+    // Removing a group takes its nicks with it.
+    let diff = encoded("_nicklist_diff",
+                       vec![group(Some('^'), "0x10", 0, "root"),
+                            group(Some('-'), "0x11", 1, "000|o")]);
+    store.apply(&RelayEvent::from_message(diff).unwrap());
+    assert_eq!(names(&store), vec!["mythmon_", "emberian"]);
+    assert!(store.buffer(BUFFER).unwrap().group("0x11").is_none());
+}
+
+#[test]
+fn fetches_nicklist_from_the_relay() {
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("nicklist", vec![encoded("", channel())]);
+    let mut client = WeechatRelay::connect(relay.address()).unwrap();
+    client.login("").unwrap();
+    let store = NicklistStore::fetch(&mut client, Some("irc.libera.#weechat")).unwrap();
+    assert_eq!(names(&store), vec!["Wraithan", "mythmon", "FlashCode"]);
+}
@@ -0,0 +1,265 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::time::Duration;
+use weechat_client::mock::MockRelay;
+use weechat_client::subscriptions::SyncOption;
+use weechat_client::{ClientError, RelayConfig, WeechatRelay};
+use std::collections::HashMap;
+use weechat_parser::events::{Completion, CompletionContext, RelayEvent};
+use weechat_parser::{WeechatData, WeechatMessage};
+
+fn connect(relay: &MockRelay) -> WeechatRelay {
+    relay.require_password("secret");
+    WeechatRelay::connect_with(&RelayConfig::tcp(&relay.address().to_string(), "secret")).unwrap()
+}
+
+fn message(id: &str, data: Vec<WeechatData>) -> WeechatMessage {
+    WeechatMessage {
+        id: id.to_owned(),
+        data: data,
+    }
+}
+
+fn last_command(relay: &MockRelay, name: &str) -> String {
+    relay.wait_for_command(name, Duration::from_secs(5)).unwrap();
+    relay.commands().into_iter().rev().find(|command| command.contains(name)).unwrap()
+}
+
+#[test]
+fn requests_get_their_own_replies() {
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("hdata", vec![message("", vec![WeechatData::Int(1)])]);
+    relay.respond_to("info", vec![message("", vec![WeechatData::Int(2)])]);
+    relay.respond_to("infolist", vec![message("", vec![WeechatData::Int(3)])]);
+    relay.respond_to("nicklist", vec![message("", vec![WeechatData::Int(4)])]);
+    let mut client = connect(&relay);
+
+    let hdata = client.hdata("buffer:gui_buffers(*) number,full_name").unwrap();
+    let info = client.info("version").unwrap();
+    let infolist = client.infolist("buffer", None, Some("irc.*")).unwrap();
+    let nicklist = client.nicklist(Some("irc.libera.#weechat")).unwrap();
+    assert_eq!(last_command(&relay, "hdata"),
+               format!("({}) hdata buffer:gui_buffers(*) number,full_name", hdata.id()));
+    assert_eq!(last_command(&relay, "infolist"),
+               format!("({}) infolist buffer 0x0 irc.*", infolist.id()));
+
+    // Waiting out of order still hands each request its own reply.
+    assert_eq!(client.wait(nicklist).unwrap().data, vec![WeechatData::Int(4)]);
+    assert_eq!(client.wait(info).unwrap().data, vec![WeechatData::Int(2)]);
+    assert_eq!(client.wait(hdata).unwrap().data, vec![WeechatData::Int(1)]);
+    assert_eq!(client.wait(infolist).unwrap().data, vec![WeechatData::Int(3)]);
+}
+
+#[test]
+fn events_keep_flowing_around_replies() {
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("hdata", vec![message("", vec![WeechatData::Int(1)])]);
+    let mut client = connect(&relay);
+    relay.send(&message("_buffer_opened", vec![])).unwrap();
+    let cancelled = client.hdata("buffer:gui_buffers(*)").unwrap();
+    let request = client.hdata("buffer:gui_buffers(*)").unwrap();
+    client.cancel(cancelled);
+    relay.send(&message("_buffer_closing", vec![])).unwrap();
+
+    assert_eq!(client.wait(request).unwrap().data, vec![WeechatData::Int(1)]);
+    assert_eq!(client.recv().unwrap(), message("_buffer_opened", vec![]));
+    assert_eq!(client.recv().unwrap(), message("_buffer_closing", vec![]));
+    assert_eq!(client.recv_timeout(Duration::from_millis(100)).unwrap(), None);
+}
+
+#[test]
+fn wait_timeout_leaves_request_pending() {
+    let relay = MockRelay::start().unwrap();
+    let mut client = connect(&relay);
+    let request = client.request("hdata hotlist:gui_hotlist(*)").unwrap();
+    assert_eq!(client.wait_timeout(&request, Duration::from_millis(100)).unwrap(), None);
+    relay.send(&message(request.id(), vec![WeechatData::Int(5)])).unwrap();
+    assert_eq!(client.wait(request).unwrap().data, vec![WeechatData::Int(5)]);
+}
+
+#[test]
+fn last_lines_come_oldest_first() {
+    let line = |date: &str, text: &str| {
+        let mut row = HashMap::new();
+        row.insert("buffer".to_owned(), WeechatData::Pointer("0x7fcab15936d0".to_owned()));
+        row.insert("date".to_owned(), WeechatData::Time(date.to_owned()));
+        row.insert("date_printed".to_owned(), WeechatData::Time(date.to_owned()));
+        row.insert("displayed".to_owned(), WeechatData::Char('\u{1}'));
+        row.insert("highlight".to_owned(), WeechatData::Char('\u{0}'));
+        row.insert("tags_array".to_owned(), WeechatData::Array(vec![]));
+        row.insert("prefix".to_owned(), WeechatData::String("Wraithan".to_owned()));
+        row.insert("message".to_owned(), WeechatData::String(text.to_owned()));
+        row
+    };
+    // Every row has the pointers of its buffer, lines, line and line_data.
+    let pointers = ["0x7fcab15936d0", "0x1", "0x2", "0x3", "0x7fcab15936d0", "0x1", "0x4", "0x5"];
+    let pointers = pointers.iter().map(|pointer| WeechatData::Pointer(pointer.to_string()));
+    let lines = WeechatData::Hdata("buffer/lines/line/line_data".to_owned(),
+                                   pointers.collect(),
+                                   vec![line("1439651883", "there"), line("1439651878", "Hey")]);
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("hdata", vec![message("", vec![lines])]);
+    let mut client = connect(&relay);
+
+    let lines = client.last_lines("0x7fcab15936d0", 2).unwrap();
+    let messages: Vec<&str> = lines.iter().map(|line| &line.message[..]).collect();
+    assert_eq!(messages, vec!["Hey", "there"]);
+    assert_eq!(lines[0].date, 1439651878);
+    assert!(last_command(&relay, "hdata")
+                .ends_with("hdata buffer:0x7fcab15936d0/own_lines/last_line(-2)/data \
+                            buffer,date,date_printed,displayed,highlight,tags_array,prefix,\
+                            message"));
+}
+
+#[test]
+fn empty_buffer_has_no_lines() {
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("hdata",
+                     vec![message("", vec![WeechatData::Hdata("".to_owned(), vec![], vec![])])]);
+    let mut client = connect(&relay);
+
+    assert_eq!(client.last_lines("0x7fcab15936d0", 0).unwrap(), vec![]);
+    assert_eq!(client.last_lines("0x7fcab15936d0", 5).unwrap(), vec![]);
+    // Nothing was asked for no lines.
+    let hdata = relay.commands().into_iter().filter(|command| command.contains(" hdata ")).count();
+    assert_eq!(hdata, 1);
+}
+
+#[test]
+fn completes_nicks() {
+    let row = vec![("context", WeechatData::String("auto".to_owned())),
+                   ("base_word", WeechatData::String("Wr".to_owned())),
+                   ("pos_start", WeechatData::Int(6)),
+                   ("pos_end", WeechatData::Int(7)),
+                   ("add_space", WeechatData::Int(1)),
+                   ("list", WeechatData::Array(vec![WeechatData::String("Wraithan".to_owned())]))];
+    let completion = WeechatData::Hdata("completion".to_owned(),
+                                        vec![WeechatData::Pointer("0x55d0ccc4b7c0".to_owned())],
+                                        vec![row.into_iter()
+                                                .map(|(key, value)| (key.to_owned(), value))
+                                                .collect()]);
+    let relay = MockRelay::start().unwrap();
+    relay.respond_to("completion", vec![message("", vec![completion])]);
+    let mut client = connect(&relay);
+
+    let completion = client.completion("irc.libera.#weechat", None, "hello Wr").unwrap();
+    assert_eq!(completion,
+               Some(Completion {
+                   context: CompletionContext::Auto,
+                   base_word: "Wr".to_owned(),
+                   pos_start: 6,
+                   pos_end: 7,
+                   add_space: true,
+                   list: vec!["Wraithan".to_owned()],
+               }));
+    assert!(last_command(&relay, "completion")
+                .ends_with(") completion irc.libera.#weechat -1 hello Wr"));
+    match client.completion("core.weechat", Some(2), "/he\nlp") {
+        Err(ClientError::InvalidArgument(_)) => {}
+        other => panic!("expected InvalidArgument, got {:?}", other),
+    }
+}
+
+#[test]
+fn sync_streams_events() {
+    let relay = MockRelay::start().unwrap();
+    relay.on_sync(vec![message("_upgrade", vec![])]);
+    let mut client = connect(&relay);
+    client.sync(&[]).unwrap();
+    client.ping("1234").unwrap();
+
+    let mut events = client.events();
+    assert_eq!(events.next().unwrap().unwrap(), RelayEvent::Upgrade);
+    assert_eq!(events.next().unwrap().unwrap(), RelayEvent::Pong("1234".to_owned()));
+}
+
+#[test]
+fn commands_are_sent_as_the_relay_expects() {
+    let relay = MockRelay::start().unwrap();
+    let mut client = connect(&relay);
+    client.sync(&["irc.libera.#weechat", "core.weechat"]).unwrap();
+    client.desync(&[]).unwrap();
+    client.send_command("core.weechat", "/help").unwrap();
+    client.send_input("irc.libera.#weechat", "/help is how\nyou get help").unwrap();
+    client.send_command("irc.libera.#weechat", "me waves").unwrap();
+    assert!(relay.wait_for_line("input irc.libera.#weechat /me waves", Duration::from_secs(5)));
+
+    let commands = relay.commands();
+    assert_eq!(&commands[commands.len() - 6..],
+               &["sync irc.libera.#weechat,core.weechat",
+                 "desync",
+                 "input core.weechat /help",
+                 "input irc.libera.#weechat //help is how",
+                 "input irc.libera.#weechat you get help",
+                 "input irc.libera.#weechat /me waves"]);
+    match client.send_command("core.weechat", "/help\n/quit") {
+        Err(ClientError::InvalidArgument(_)) => {}
+        other => panic!("expected InvalidArgument, got {:?}", other),
+    }
+}
+
+#[test]
+fn sync_options_are_sent() {
+    let relay = MockRelay::start().unwrap();
+    let mut client = connect(&relay);
+    client.sync_with(&[], &[SyncOption::Buffers, SyncOption::Upgrade]).unwrap();
+    client.sync_with(&["irc.libera.#weechat"], &[SyncOption::Buffer]).unwrap();
+    client.desync_with(&["irc.libera.#weechat"], &[SyncOption::Buffer]).unwrap();
+    relay.wait_for_command("desync", Duration::from_secs(5)).unwrap();
+
+    let commands = relay.commands();
+    assert_eq!(&commands[commands.len() - 3..],
+               &["sync * buffers,upgrade",
+                 "sync irc.libera.#weechat buffer",
+                 "desync irc.libera.#weechat buffer"]);
+}
+
+#[test]
+fn messages_end_when_relay_closes() {
+    let relay = MockRelay::start().unwrap();
+    let mut client = connect(&relay);
+    relay.send(&message("_buffer_cleared", vec![])).unwrap();
+    relay.disconnect_all();
+
+    let messages: Vec<WeechatMessage> = client.messages().map(Result::unwrap).collect();
+    assert_eq!(messages, vec![message("_buffer_cleared", vec![])]);
+    match client.recv() {
+        Err(ClientError::Closed) => {}
+        other => panic!("expected Closed, got {:?}", other),
+    }
+}
+
+#[test]
+fn bad_messages_are_dropped() {
+    let relay = MockRelay::start().unwrap();
+    let mut client = connect(&relay);
+    relay.send_raw(&[0, 0, 0, 5, 9]).unwrap();
+    relay.send(&message("_buffer_cleared", vec![])).unwrap();
+    match client.recv() {
+        Err(ClientError::Parse(_)) => {}
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    assert_eq!(client.recv().unwrap(), message("_buffer_cleared", vec![]));
+
+    // A length too short for any message leaves nothing to resync on.
+    relay.send_raw(&[0, 0, 0, 0]).unwrap();
+    relay.send(&message("_buffer_cleared", vec![])).unwrap();
+    match client.recv() {
+        Err(ClientError::Parse(_)) => {}
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    match client.recv() {
+        Err(ClientError::Closed) => {}
+        other => panic!("expected Closed, got {:?}", other),
+    }
+}
+
+#[test]
+fn quit_closes_the_connection() {
+    let relay = MockRelay::start().unwrap();
+    let mut client = connect(&relay);
+    client.quit().unwrap();
+    relay.wait_for_command("quit", Duration::from_secs(5)).unwrap();
+    assert!(client.recv().is_err());
+}
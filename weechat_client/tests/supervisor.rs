@@ -0,0 +1,216 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::net::TcpListener;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use weechat_client::auth::AuthError;
+use weechat_client::mock::MockRelay;
+use weechat_client::{Backoff, ClientError, ConnectionState, Heartbeat, RelayConfig, Supervisor};
+use weechat_parser::WeechatMessage;
+
+fn backoff(max_attempts: Option<u32>) -> Backoff {
+    Backoff {
+        initial: Duration::from_millis(10),
+        max: Duration::from_millis(50),
+        max_attempts: max_attempts,
+        ..Backoff::default()
+    }
+}
+
+fn states(rx: &Receiver<ConnectionState>) -> Vec<ConnectionState> {
+    rx.try_iter().collect()
+}
+
+#[test]
+fn reconnects_and_syncs_again() {
+    let relay = MockRelay::start().unwrap();
+    relay.require_password("secret");
+    let address = relay.address();
+    let mut supervisor = Supervisor::new(RelayConfig::tcp(&address.to_string(), "secret"),
+                                         backoff(Some(5)));
+    let rx = supervisor.states();
+    supervisor.connect().unwrap();
+    supervisor.sync(&["irc.libera.#weechat"]).unwrap();
+    supervisor.desync(&["irc.libera.#weechat"]).unwrap();
+    supervisor.sync(&["core.weechat"]).unwrap();
+    relay.wait_for_command("desync", Duration::from_secs(5)).unwrap();
+    assert_eq!(states(&rx),
+               vec![ConnectionState::Connecting { attempt: 1 }, ConnectionState::Connected]);
+
+    // Kill the relay mid-session and bring it back on the same port.
+    drop(relay);
+    let relay = MockRelay::start_on(address).unwrap();
+    relay.require_password("secret");
+    let upgrade = WeechatMessage {
+        id: "_upgrade".to_owned(),
+        data: vec![],
+    };
+    relay.on_sync(vec![upgrade.clone()]);
+
+    assert_eq!(supervisor.recv().unwrap(), upgrade);
+    match &states(&rx)[..] {
+        [ConnectionState::Disconnected { .. },
+         ConnectionState::Connecting { attempt: 1 },
+         ConnectionState::Connected] => {}
+        other => panic!("unexpected states {:?}", other),
+    }
+    assert!(relay.wait_for_line("sync core.weechat", Duration::from_secs(5)));
+    let commands = relay.commands();
+    assert!(commands.iter().any(|command| command.starts_with("init ")));
+    // Only what is still synced is synced again.
+    assert_eq!(commands.last().unwrap(), "sync core.weechat");
+    assert!(!commands.iter().any(|command| command.contains("#weechat")));
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    // Nothing listens on a port we just let go of.
+    let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let mut supervisor = Supervisor::new(RelayConfig::tcp(&address.to_string(), ""),
+                                         backoff(Some(3)));
+    let rx = supervisor.states();
+    assert!(supervisor.connect().is_err());
+    assert_eq!(states(&rx),
+               vec![ConnectionState::Connecting { attempt: 1 },
+                    ConnectionState::Connecting { attempt: 2 },
+                    ConnectionState::Connecting { attempt: 3 },
+                    ConnectionState::GaveUp { attempts: 3 }]);
+    assert!(!supervisor.is_connected());
+}
+
+#[test]
+fn gives_up_on_a_wrong_password() {
+    let relay = MockRelay::start().unwrap();
+    relay.require_password("secret");
+    let mut supervisor = Supervisor::new(RelayConfig::tcp(&relay.address().to_string(), "nope"),
+                                         backoff(Some(5)));
+    let rx = supervisor.states();
+    match supervisor.connect() {
+        Err(ClientError::Auth(AuthError::Rejected)) => {}
+        other => panic!("expected Rejected, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!(states(&rx),
+               vec![ConnectionState::Connecting { attempt: 1 },
+                    ConnectionState::GaveUp { attempts: 1 }]);
+}
+
+#[test]
+fn retries_a_login_cut_short() {
+    let relay = MockRelay::start().unwrap();
+    relay.require_password("secret");
+    let address = relay.address();
+    let mut supervisor = Supervisor::new(RelayConfig::tcp(&address.to_string(), "secret"),
+                                         backoff(Some(50)));
+    let rx = supervisor.states();
+    supervisor.connect().unwrap();
+    supervisor.sync(&["core.weechat"]).unwrap();
+    relay.wait_for_command("sync", Duration::from_secs(5)).unwrap();
+    states(&rx);
+    drop(relay);
+
+    // The next relay goes away after the handshake, before the login is confirmed, which looks
+    // just like a rejected password.
+    let (done_tx, done_rx) = channel();
+    let restarter = thread::spawn(move || {
+        let relay = MockRelay::start_on(address).unwrap();
+        relay.require_password("secret");
+        relay.wait_for_command("handshake", Duration::from_secs(5)).unwrap();
+        relay.set_frozen(true);
+        relay.wait_for_command("init", Duration::from_secs(5)).unwrap();
+        drop(relay);
+
+        let relay = MockRelay::start_on(address).unwrap();
+        relay.require_password("secret");
+        let _ = done_rx.recv();
+        assert!(relay.wait_for_line("sync core.weechat", Duration::from_secs(5)));
+        relay.commands()
+    });
+
+    while supervisor.is_connected() {
+        supervisor.recv_timeout(Duration::from_millis(100)).unwrap();
+    }
+    supervisor.connect().unwrap();
+    done_tx.send(()).unwrap();
+    assert!(restarter.join().unwrap().iter().any(|command| command.starts_with("init ")));
+    // The attempt cut short was retried.
+    match &states(&rx)[..] {
+        [ConnectionState::Disconnected { .. },
+         ref retries @ ..,
+         ConnectionState::Connecting { attempt },
+         ConnectionState::Connected] => {
+            assert!(*attempt >= 2);
+            assert!(retries.iter().all(|state| match *state {
+                ConnectionState::Connecting { .. } => true,
+                _ => false,
+            }));
+        }
+        other => panic!("unexpected states {:?}", other),
+    }
+}
+
+#[test]
+fn gives_up_on_a_changed_password() {
+    let relay = MockRelay::start().unwrap();
+    relay.require_password("secret");
+    let address = relay.address();
+    let mut supervisor = Supervisor::new(RelayConfig::tcp(&address.to_string(), "secret"),
+                                         backoff(None));
+    let rx = supervisor.states();
+    supervisor.connect().unwrap();
+    drop(relay);
+    let relay = MockRelay::start_on(address).unwrap();
+    relay.require_password("changed");
+
+    while supervisor.is_connected() {
+        supervisor.recv_timeout(Duration::from_millis(100)).unwrap();
+    }
+    states(&rx);
+    match supervisor.connect() {
+        Err(ClientError::Auth(AuthError::Rejected)) => {}
+        other => panic!("expected Rejected, got {:?}", other.map(|_| ())),
+    }
+    // A few rejections are retried in case the login was cut short, then it stops.
+    assert_eq!(states(&rx),
+               vec![ConnectionState::Connecting { attempt: 1 },
+                    ConnectionState::Connecting { attempt: 2 },
+                    ConnectionState::Connecting { attempt: 3 },
+                    ConnectionState::Connecting { attempt: 4 },
+                    ConnectionState::GaveUp { attempts: 4 }]);
+}
+
+#[test]
+fn heartbeat_notices_a_dead_connection() {
+    let relay = MockRelay::start().unwrap();
+    let mut supervisor = Supervisor::new(RelayConfig::tcp(&relay.address().to_string(), ""),
+                                         backoff(Some(5)));
+    supervisor.set_heartbeat(Heartbeat {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(200),
+    });
+    let rx = supervisor.states();
+    supervisor.connect().unwrap();
+    // Reads time out once a second, so heartbeats are only checked that often. Our own
+    // `_pong`s never come out of `recv`.
+    assert_eq!(supervisor.recv_timeout(Duration::from_millis(2500)).unwrap(), None);
+    assert!(supervisor.latency().is_some());
+    assert!(relay.commands().iter().any(|command| command.starts_with("ping keepalive-")));
+
+    relay.set_frozen(true);
+    for _ in 0..10 {
+        if !supervisor.is_connected() {
+            break;
+        }
+        assert_eq!(supervisor.recv_timeout(Duration::from_millis(100)).unwrap(), None);
+    }
+    match states(&rx).last() {
+        Some(&ConnectionState::Disconnected { ref reason }) => assert!(reason.contains("_pong")),
+        other => panic!("expected a disconnect, got {:?}", other),
+    }
+
+    relay.set_frozen(false);
+    supervisor.connect().unwrap();
+    assert_eq!(states(&rx),
+               vec![ConnectionState::Connecting { attempt: 1 }, ConnectionState::Connected]);
+}
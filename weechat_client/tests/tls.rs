@@ -0,0 +1,152 @@
+#![cfg(feature = "tls")]
+
+extern crate rcgen;
+extern crate rustls;
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use weechat_client::mock;
+use weechat_client::{ClientError, WeechatRelay};
+use weechat_client::tls::{fingerprint, TlsError, TlsVerification};
+use weechat_parser::Compression;
+
+struct TlsRelay {
+    address: SocketAddr,
+    certificate: CertificateDer<'static>,
+    pem: String,
+}
+
+/// A TLS listener with a fresh self-signed certificate for localhost that answers `handshake`
+/// with a plain password and `ping` with `_pong`, enough for `login` to go through.
+fn start_tls_relay() -> TlsRelay {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let certificate = generated.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(generated.key_pair.serialize_der()));
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+                     .with_safe_default_protocol_versions()
+                     .unwrap()
+                     .with_no_client_auth()
+                     .with_single_cert(vec![certificate.clone()], key)
+                     .unwrap();
+    let config = Arc::new(config);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let connection = ServerConnection::new(config.clone()).unwrap();
+            thread::spawn(move || serve(StreamOwned::new(connection, stream)));
+        }
+    });
+    TlsRelay {
+        address: address,
+        certificate: certificate,
+        pem: generated.cert.pem(),
+    }
+}
+
+fn serve<S: Read + Write>(stream: S) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+        if let Some(reply) = mock::login_reply(&line) {
+            let stream = reader.get_mut();
+            stream.write_all(&reply.to_bytes(Compression::None).unwrap()).unwrap();
+            stream.flush().unwrap();
+        }
+        line.clear();
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    env::temp_dir().join(format!("weechat-{}-{}-{}", name, since_epoch.as_secs(),
+                                 since_epoch.subsec_nanos()))
+}
+
+#[test]
+fn connects_with_ca_file() {
+    let relay = start_tls_relay();
+    let ca_file = temp_path("ca.pem");
+    fs::write(&ca_file, &relay.pem).unwrap();
+    let mut client = WeechatRelay::connect_tls(relay.address,
+                                               "localhost",
+                                               &TlsVerification::CaFile(ca_file.clone()))
+                         .unwrap();
+    client.login("").unwrap();
+    fs::remove_file(&ca_file).unwrap();
+}
+
+#[test]
+fn rejects_self_signed_with_system_roots() {
+    let relay = start_tls_relay();
+    match WeechatRelay::connect_tls(relay.address, "localhost", &TlsVerification::SystemRoots) {
+        Err(ClientError::Tls(TlsError::Tls(rustls::Error::InvalidCertificate(_)))) => {}
+        other => panic!("expected an invalid certificate, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn pins_fingerprint() {
+    let relay = start_tls_relay();
+    let pinned = TlsVerification::Fingerprint(fingerprint(&relay.certificate));
+    let mut client = WeechatRelay::connect_tls(relay.address, "localhost", &pinned).unwrap();
+    client.login("").unwrap();
+
+    let wrong = TlsVerification::Fingerprint(vec![0; 32]);
+    match WeechatRelay::connect_tls(relay.address, "localhost", &wrong) {
+        Err(ClientError::Tls(TlsError::FingerprintMismatch { expected, actual })) => {
+            assert_eq!(expected, vec![0; 32]);
+            assert_eq!(actual, fingerprint(&relay.certificate));
+        }
+        other => panic!("expected a fingerprint mismatch, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn trusts_on_first_use() {
+    let relay = start_tls_relay();
+    let store = temp_path("known-relays");
+    let tofu = TlsVerification::TrustOnFirstUse(store.clone());
+    WeechatRelay::connect_tls(relay.address, "localhost", &tofu).unwrap().login("").unwrap();
+    assert_eq!(fs::read_to_string(&store).unwrap().lines().count(), 1);
+    // The stored fingerprint is used from now on.
+    WeechatRelay::connect_tls(relay.address, "localhost", &tofu).unwrap().login("").unwrap();
+    let stored = fs::read_to_string(&store).unwrap();
+    assert_eq!(stored.lines().count(), 1);
+    assert!(stored.starts_with(&format!("localhost:{} ", relay.address.port())));
+
+    // Another relay on the same host but another port has its own certificate.
+    let other = start_tls_relay();
+    WeechatRelay::connect_tls(other.address, "localhost", &tofu).unwrap().login("").unwrap();
+    assert_eq!(fs::read_to_string(&store).unwrap().lines().count(), 2);
+
+    // A certificate that changed since the first connection is refused.
+    let impostor = start_tls_relay();
+    let mut file = fs::OpenOptions::new().append(true).open(&store).unwrap();
+    writeln!(file, "localhost:{} {}", impostor.address.port(), "00".repeat(32)).unwrap();
+    match WeechatRelay::connect_tls(impostor.address, "localhost", &tofu) {
+        Err(ClientError::Tls(TlsError::FingerprintMismatch { expected, actual })) => {
+            assert_eq!(expected, vec![0; 32]);
+            assert_eq!(actual, fingerprint(&impostor.certificate));
+        }
+        other => panic!("expected a fingerprint mismatch, got {:?}", other.err()),
+    }
+    fs::remove_file(&store).unwrap();
+}
@@ -0,0 +1,100 @@
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use weechat_client::WeechatRelay;
+use weechat_client::auth::HashAlgorithm;
+use weechat_client::mock;
+use weechat_client::mock::MockRelay;
+#[cfg(unix)]
+use weechat_client::transport::CommandTransport;
+use weechat_client::transport::{memory_pair, StreamTransport, Transport};
+use weechat_parser::Compression;
+
+fn reply_to(line: &str) -> Option<Vec<u8>> {
+    mock::login_reply(line).map(|message| message.to_bytes(Compression::None).unwrap())
+}
+
+#[test]
+fn logs_in_over_memory_pair() {
+    let (client, mut relay) = memory_pair();
+    let server = thread::spawn(move || {
+        let mut received = vec![];
+        let mut buffer = [0; 1024];
+        loop {
+            match relay.read_bytes(&mut buffer) {
+                Ok(0) => return String::from_utf8(received).unwrap(),
+                Ok(count) => {
+                    // Every command arrives in one write_command call.
+                    let line = String::from_utf8(buffer[..count].to_vec()).unwrap();
+                    received.extend_from_slice(line.as_bytes());
+                    if let Some(reply) = reply_to(&line) {
+                        relay.write_bytes(&reply).unwrap();
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    });
+
+    let mut client = WeechatRelay::new(client);
+    client.login("secret").unwrap();
+    client.quit().unwrap();
+    let received = server.join().unwrap();
+    let lines: Vec<&str> = received.lines().collect();
+    assert_eq!(&lines[1..], &["init password=secret", "ping login", "quit"]);
+}
+
+#[test]
+fn logs_in_through_stream_transport() {
+    let relay = MockRelay::start().unwrap();
+    relay.require_password("secret");
+    relay.set_hash_algorithms(vec![HashAlgorithm::Pbkdf2Sha256]);
+    let stream = TcpStream::connect(relay.address()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    let mut client = WeechatRelay::new(StreamTransport::new(stream));
+    client.login("secret").unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn logs_in_over_unix_socket() {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let path = env::temp_dir().join(format!("weechat-relay-{}.sock", since_epoch.subsec_nanos()));
+    let listener = UnixListener::bind(&path).unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut out_stream = stream.try_clone().unwrap();
+        for line in BufReader::new(stream).lines() {
+            if let Some(reply) = reply_to(&line.unwrap()) {
+                out_stream.write_all(&reply).unwrap();
+            }
+        }
+    });
+
+    let mut client = WeechatRelay::connect_unix(&path).unwrap();
+    client.login("").unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn command_transport_uses_child_stdio() {
+    let mut transport = CommandTransport::spawn(&mut Command::new("cat")).unwrap();
+    transport.write_command("ping 1234\n").unwrap();
+    let mut buffer = [0; 64];
+    let count = transport.read_bytes(&mut buffer).unwrap();
+    assert_eq!(&buffer[..count], b"ping 1234\n");
+    transport.close().unwrap();
+    assert_eq!(transport.read_bytes(&mut buffer).unwrap(), 0);
+}
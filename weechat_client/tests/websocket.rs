@@ -0,0 +1,100 @@
+extern crate tungstenite;
+extern crate weechat_client;
+extern crate weechat_parser;
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use tungstenite::Message;
+use weechat_client::WeechatRelay;
+use weechat_client::mock;
+use weechat_parser::{Compression, WeechatMessage};
+
+fn handshake_reply() -> Vec<u8> {
+    mock::plain_handshake().to_bytes(Compression::Zlib).unwrap()
+}
+
+fn pong(data: &str) -> Vec<u8> {
+    mock::pong(data).to_bytes(Compression::None).unwrap()
+}
+
+/// Serve `serve` on the first stream accepted by a fresh local listener.
+fn listen<F>(serve: F) -> SocketAddr
+    where F: FnOnce(tungstenite::WebSocket<std::net::TcpStream>) + Send + 'static
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(tungstenite::accept(stream).unwrap());
+    });
+    address
+}
+
+/// A WebSocket relay answering `handshake` and `ping`, reporting every text frame it gets. The
+/// handshake reply is split over two frames and the pong shares a frame with another message,
+/// like a proxy might rechunk them.
+fn start_ws_relay() -> (SocketAddr, Receiver<String>) {
+    let (tx, rx) = channel();
+    let address = listen(move |mut socket| {
+        loop {
+            let text = match socket.read() {
+                Ok(Message::Text(text)) => text.as_str().to_owned(),
+                Ok(_) => continue,
+                Err(_) => return,
+            };
+            let _ = tx.send(text.clone());
+            if text.starts_with("(handshake) handshake") {
+                let reply = handshake_reply();
+                let (first, second) = reply.split_at(7);
+                socket.send(Message::binary(first.to_vec())).unwrap();
+                socket.send(Message::binary(second.to_vec())).unwrap();
+            } else if text.starts_with("ping ") {
+                let mut reply = WeechatMessage {
+                                    id: "_buffer_opened".to_owned(),
+                                    data: vec![],
+                                }
+                                .to_bytes(Compression::None)
+                                .unwrap();
+                reply.extend(pong(text[5..].trim()));
+                socket.send(Message::binary(reply)).unwrap();
+            }
+        }
+    });
+    (address, rx)
+}
+
+#[test]
+fn logs_in_over_websocket() {
+    let (address, rx) = start_ws_relay();
+    let url = format!("ws://{}/weechat", address);
+    let mut client = WeechatRelay::connect_websocket(&url).unwrap();
+    client.login("pass,word").unwrap();
+
+    let frames: Vec<String> = rx.try_iter().collect();
+    assert_eq!(frames.len(), 3);
+    assert!(frames[0].starts_with("(handshake) handshake password_hash_algo="));
+    // One command per text frame, newline included.
+    assert_eq!(frames[1], "init password=pass\\,word\n");
+    assert_eq!(frames[2], "ping login\n");
+}
+
+#[test]
+fn closed_websocket_rejects_login() {
+    let address = listen(|mut socket| {
+        let _ = socket.read();
+        socket.send(Message::binary(handshake_reply())).unwrap();
+        let _ = socket.read();
+        let _ = socket.close(None);
+        thread::sleep(Duration::from_millis(100));
+    });
+    let mut client = WeechatRelay::connect_websocket(&format!("ws://{}/weechat", address))
+                         .unwrap();
+    assert!(client.login("wrong").is_err());
+}
+
+#[test]
+fn rejects_non_websocket_urls() {
+    assert!(WeechatRelay::connect_websocket("http://localhost/weechat").is_err());
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+use byteorder::{WriteBytesExt, BigEndian};
+use flate2;
+use flate2::write::ZlibEncoder;
+use zstd;
+use errors::WeechatParseError;
+use errors::ErrorKind::{UnencodableData, UnknownCompression};
+use {Compression, WeechatData, WeechatMessage};
+
+pub fn encode(message: &WeechatMessage,
+              compression: Compression)
+              -> Result<Vec<u8>, WeechatParseError> {
+    let mut raw_data = vec![];
+    try!(write_string_32bit_length(&mut raw_data, Some(&message.id)));
+    for element in &message.data {
+        raw_data.extend_from_slice(element_type(element).as_bytes());
+        try!(encode_element(&mut raw_data, element));
+    }
+
+    let (flag, payload) = match compression {
+        Compression::None => (0, raw_data),
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(vec![], flate2::Compression::default());
+            try!(encoder.write_all(&raw_data));
+            (1, try!(encoder.finish()))
+        }
+        Compression::Zstd => (2, try!(zstd::encode_all(&raw_data[..], 0))),
+        Compression::Unknown(flag) => {
+            fail!((UnknownCompression,
+                   "Can't encode with unfamiliar compression",
+                   format!("found compression flag {}", flag)))
+        }
+    };
+
+    let mut buffer = Vec::with_capacity(payload.len() + 5);
+    try!(buffer.write_u32::<BigEndian>((payload.len() + 5) as u32));
+    buffer.push(flag);
+    buffer.extend(payload);
+    Ok(buffer)
+}
+
+pub fn element_type(element: &WeechatData) -> &'static str {
+    match *element {
+        WeechatData::Char(_) => "chr",
+        WeechatData::Int(_) => "int",
+        WeechatData::Long(_) => "lon",
+        WeechatData::String(_) | WeechatData::StringNull => "str",
+        WeechatData::Buffer(_) | WeechatData::BufferNull => "buf",
+        WeechatData::Pointer(_) => "ptr",
+        WeechatData::Time(_) => "tim",
+        WeechatData::Array(_) => "arr",
+        WeechatData::Hashtable(_, _, _) => "htb",
+        WeechatData::Hdata(_, _, _) => "hda",
+        WeechatData::Info(_, _) => "inf",
+        WeechatData::Infolist(_, _) => "inl",
+    }
+}
+
+fn encode_element(buffer: &mut Vec<u8>, element: &WeechatData) -> Result<(), WeechatParseError> {
+    match *element {
+        WeechatData::Char(value) => {
+            if value as u32 > 255 {
+                fail!((UnencodableData,
+                       "Char doesn't fit in a single byte",
+                       format!("found char {:?}", value)))
+            }
+            buffer.push(value as u8);
+        }
+        WeechatData::Int(value) => try!(buffer.write_i32::<BigEndian>(value)),
+        WeechatData::Long(value) => try!(write_string_8bit_length(buffer, &value.to_string())),
+        WeechatData::String(ref value) | WeechatData::Buffer(ref value) => {
+            try!(write_string_32bit_length(buffer, Some(value)))
+        }
+        WeechatData::StringNull | WeechatData::BufferNull => {
+            try!(write_string_32bit_length(buffer, None))
+        }
+        WeechatData::Pointer(ref value) => try!(write_pointer(buffer, value)),
+        WeechatData::Time(ref value) => try!(write_string_8bit_length(buffer, value)),
+        WeechatData::Array(ref values) => try!(write_array(buffer, values)),
+        WeechatData::Hashtable(ref key_type, ref value_type, ref entries) => {
+            try!(write_hashtable(buffer, key_type, value_type, entries))
+        }
+        WeechatData::Hdata(ref name, ref pointers, ref rows) => {
+            try!(write_hdata(buffer, name, pointers, rows))
+        }
+        WeechatData::Info(ref name, ref value) => {
+            try!(write_string_32bit_length(buffer, Some(name)));
+            try!(write_string_32bit_length(buffer, value.as_ref()));
+        }
+        WeechatData::Infolist(ref name, ref items) => try!(write_infolist(buffer, name, items)),
+    }
+    Ok(())
+}
+
+fn encode_typed_element(buffer: &mut Vec<u8>,
+                        expected_type: &str,
+                        element: &WeechatData)
+                        -> Result<(), WeechatParseError> {
+    if element_type(element) != expected_type {
+        fail!((UnencodableData,
+               "Element doesn't match its container's type",
+               format!("expected {} but found {:?}", expected_type, element)))
+    }
+    encode_element(buffer, element)
+}
+
+fn write_string_8bit_length(buffer: &mut Vec<u8>, value: &str) -> Result<(), WeechatParseError> {
+    if value.len() > 255 {
+        fail!((UnencodableData,
+               "String is too long for an 8 bit length",
+               format!("found length {}", value.len())))
+    }
+    buffer.push(value.len() as u8);
+    buffer.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn write_string_32bit_length(buffer: &mut Vec<u8>,
+                             value: Option<&String>)
+                             -> Result<(), WeechatParseError> {
+    match value {
+        Some(string) => {
+            try!(buffer.write_i32::<BigEndian>(string.len() as i32));
+            buffer.extend_from_slice(string.as_bytes());
+        }
+        None => try!(buffer.write_i32::<BigEndian>(-1)),
+    }
+    Ok(())
+}
+
+fn write_pointer(buffer: &mut Vec<u8>, value: &str) -> Result<(), WeechatParseError> {
+    // Pointers are sent without their 0x prefix.
+    let stripped = if value.starts_with("0x") {
+        &value[2..]
+    } else {
+        value
+    };
+    write_string_8bit_length(buffer, stripped)
+}
+
+fn write_array(buffer: &mut Vec<u8>, values: &[WeechatData]) -> Result<(), WeechatParseError> {
+    let array_type = values.first().map(element_type).unwrap_or("int");
+    buffer.extend_from_slice(array_type.as_bytes());
+    try!(buffer.write_i32::<BigEndian>(values.len() as i32));
+    for value in values {
+        try!(encode_typed_element(buffer, array_type, value));
+    }
+    Ok(())
+}
+
+fn write_hashtable(buffer: &mut Vec<u8>,
+                   key_type: &str,
+                   value_type: &str,
+                   entries: &[(WeechatData, WeechatData)])
+                   -> Result<(), WeechatParseError> {
+    if key_type.len() != 3 || value_type.len() != 3 {
+        fail!((UnencodableData,
+               "Hashtable types must be 3 characters",
+               format!("found {:?} and {:?}", key_type, value_type)))
+    }
+    buffer.extend_from_slice(key_type.as_bytes());
+    buffer.extend_from_slice(value_type.as_bytes());
+    try!(buffer.write_i32::<BigEndian>(entries.len() as i32));
+    for &(ref key, ref value) in entries {
+        try!(encode_typed_element(buffer, key_type, key));
+        try!(encode_typed_element(buffer, value_type, value));
+    }
+    Ok(())
+}
+
+fn write_hdata(buffer: &mut Vec<u8>,
+               name: &String,
+               pointers: &[WeechatData],
+               rows: &[HashMap<String, WeechatData>])
+               -> Result<(), WeechatParseError> {
+    let pointer_count = name.match_indices('/').count() + 1;
+    if pointers.len() != pointer_count * rows.len() {
+        fail!((UnencodableData,
+               "Hdata has the wrong number of pointers for its path",
+               format!("expected {} but found {}", pointer_count * rows.len(), pointers.len())))
+    }
+
+    // Rows are stored in HashMaps, so sort the keys to get a stable encoding.
+    let mut keys: Vec<(&String, &'static str)> = match rows.first() {
+        Some(row) => row.iter().map(|(key, value)| (key, element_type(value))).collect(),
+        None => vec![],
+    };
+    keys.sort();
+    let keys_string = keys.iter()
+                          .map(|&(key, key_type)| format!("{}:{}", key, key_type))
+                          .collect::<Vec<String>>()
+                          .join(",");
+
+    try!(write_string_32bit_length(buffer, Some(name)));
+    try!(write_string_32bit_length(buffer, Some(&keys_string)));
+    try!(buffer.write_i32::<BigEndian>(rows.len() as i32));
+    for (index, row) in rows.iter().enumerate() {
+        for pointer in &pointers[index * pointer_count..(index + 1) * pointer_count] {
+            match *pointer {
+                WeechatData::Pointer(ref value) => try!(write_pointer(buffer, value)),
+                ref other => {
+                    fail!((UnencodableData,
+                           "Hdata pointers must be Pointer elements",
+                           format!("found {:?}", other)))
+                }
+            }
+        }
+        if row.len() != keys.len() {
+            fail!((UnencodableData,
+                   "Hdata rows must all have the same keys",
+                   format!("row {} has {} keys, expected {}", index, row.len(), keys.len())))
+        }
+        for &(key, key_type) in &keys {
+            match row.get(key) {
+                Some(value) => try!(encode_typed_element(buffer, key_type, value)),
+                None => {
+                    fail!((UnencodableData,
+                           "Hdata rows must all have the same keys",
+                           format!("row {} is missing key {:?}", index, key)))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_infolist(buffer: &mut Vec<u8>,
+                  name: &String,
+                  items: &[Vec<(String, WeechatData)>])
+                  -> Result<(), WeechatParseError> {
+    try!(write_string_32bit_length(buffer, Some(name)));
+    try!(buffer.write_i32::<BigEndian>(items.len() as i32));
+    for item in items {
+        try!(buffer.write_i32::<BigEndian>(item.len() as i32));
+        for &(ref variable_name, ref value) in item {
+            try!(write_string_32bit_length(buffer, Some(variable_name)));
+            buffer.extend_from_slice(element_type(value).as_bytes());
+            try!(encode_element(buffer, value));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_encode_test_data() {
+    // The uncompressed payload of the test command's response, as found in the parser tests.
+    let message = WeechatMessage {
+        id: "test".to_owned(),
+        data: vec![WeechatData::Char('A'),
+                   WeechatData::Int(123456),
+                   WeechatData::Long(-1234567890),
+                   WeechatData::StringNull,
+                   WeechatData::Pointer("0x1234abcd".to_owned()),
+                   WeechatData::Array(vec![WeechatData::String("abc".to_owned()),
+                                           WeechatData::String("de".to_owned())])],
+    };
+    let encoded = encode(&message, Compression::None).unwrap();
+    assert_eq!(encoded,
+               vec![0, 0, 0, 81, 0, 0, 0, 0, 4, 116, 101, 115, 116, 99, 104, 114, 65, 105, 110,
+                    116, 0, 1, 226, 64, 108, 111, 110, 11, 45, 49, 50, 51, 52, 53, 54, 55, 56,
+                    57, 48, 115, 116, 114, 255, 255, 255, 255, 112, 116, 114, 8, 49, 50, 51, 52,
+                    97, 98, 99, 100, 97, 114, 114, 115, 116, 114, 0, 0, 0, 2, 0, 0, 0, 3, 97, 98,
+                    99, 0, 0, 0, 2, 100, 101]);
+}
+
+#[test]
+fn test_encode_rejects_bad_data() {
+    let wide_char = WeechatMessage {
+        id: "test".to_owned(),
+        data: vec![WeechatData::Char('\u{2603}')],
+    };
+    assert_eq!(encode(&wide_char, Compression::None).unwrap_err().kind(), UnencodableData);
+
+    let mixed_array = WeechatMessage {
+        id: "test".to_owned(),
+        data: vec![WeechatData::Array(vec![WeechatData::Int(1), WeechatData::Long(2)])],
+    };
+    assert_eq!(encode(&mixed_array, Compression::None).unwrap_err().kind(), UnencodableData);
+
+    let missing_pointers = WeechatMessage {
+        id: "test".to_owned(),
+        data: vec![WeechatData::Hdata("buffer/lines".to_owned(),
+                                      vec![WeechatData::Pointer("0x1".to_owned())],
+                                      vec![HashMap::new()])],
+    };
+    assert_eq!(encode(&missing_pointers, Compression::None).unwrap_err().kind(),
+               UnencodableData);
+
+    let empty = WeechatMessage { id: "test".to_owned(), data: vec![] };
+    assert_eq!(encode(&empty, Compression::Unknown(9)).unwrap_err().kind(), UnknownCompression);
+}
@@ -3,7 +3,6 @@ use std::error::Error as CoreError;
 use std::fmt;
 use std::io::Error as IOError;
 use std::num::ParseIntError;
-use byteorder::Error as ByteOrderError;
 
 
 #[macro_export]
@@ -30,6 +29,10 @@ pub enum ErrorKind {
     NotImplemented,
     UnknownId,
     UnknownType,
+    UnknownCompression,
+    UnencodableData,
+    MissingField,
+    UnexpectedType,
 }
 
 impl WeechatParseError {
@@ -103,16 +106,6 @@ impl From<(ErrorKind, &'static str, String)> for WeechatParseError {
     }
 }
 
-impl From<ByteOrderError> for WeechatParseError {
-    fn from(error: ByteOrderError) -> WeechatParseError {
-        WeechatParseError {
-            repr: ErrorRepr::WithDescriptionAndDetail(ErrorKind::MalformedBinaryParse,
-                                                      "failed to parse binary data",
-                                                      error.description().to_owned()),
-        }
-    }
-}
-
 impl From<IOError> for WeechatParseError {
     fn from(error: IOError) -> WeechatParseError {
         WeechatParseError {
@@ -0,0 +1,760 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use errors::WeechatParseError;
+use errors::ErrorKind::{MissingField, UnexpectedType};
+use {WeechatData, WeechatMessage};
+
+/// A line printed in a buffer, as sent by `_buffer_line_added` or read from a buffer's lines.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct LineAdded {
+    /// Pointer of the buffer the line was printed in.
+    pub buffer: String,
+    pub date: i64,
+    pub date_printed: i64,
+    pub displayed: bool,
+    pub highlight: bool,
+    pub tags: Vec<String>,
+    pub prefix: String,
+    pub message: String,
+}
+
+impl LineAdded {
+    pub fn from_hdata_row(row: &HashMap<String, WeechatData>) -> Result<LineAdded, WeechatParseError> {
+        Ok(LineAdded {
+            buffer: try!(get_pointer(row, "buffer")),
+            date: try!(get_time(row, "date")),
+            date_printed: try!(get_time(row, "date_printed")),
+            displayed: try!(get_flag(row, "displayed")),
+            highlight: try!(get_flag(row, "highlight")),
+            tags: try!(get_string_array(row, "tags_array")),
+            prefix: try!(get_string(row, "prefix")),
+            message: try!(get_string(row, "message")),
+        })
+    }
+
+    /// Every line in the message, the relay can send more than one line per message. An hdata
+    /// without rows, what the relay answers for an empty buffer, has no lines.
+    pub fn all_from(message: &WeechatMessage) -> Result<Vec<LineAdded>, WeechatParseError> {
+        if is_empty_hdata(message) {
+            return Ok(vec![]);
+        }
+        let rows = try!(get_hdata_rows(message, "line_data"));
+        rows.iter().map(LineAdded::from_hdata_row).collect()
+    }
+}
+
+impl<'a> TryFrom<&'a WeechatMessage> for LineAdded {
+    type Error = WeechatParseError;
+
+    fn try_from(message: &'a WeechatMessage) -> Result<LineAdded, WeechatParseError> {
+        let rows = try!(get_hdata_rows(message, "line_data"));
+        match rows.first() {
+            Some(row) => LineAdded::from_hdata_row(row),
+            None => fail!((MissingField, "Hdata has no rows", message.id.clone())),
+        }
+    }
+}
+
+/// Buffer fields sent with the `_buffer_*` events. Only `pointer`, `number` and `full_name` are
+/// sent with every event, the rest depend on the event.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BufferChange {
+    pub pointer: String,
+    pub number: i32,
+    pub full_name: String,
+    pub short_name: Option<String>,
+    pub title: Option<String>,
+    pub nicklist: Option<bool>,
+    pub buffer_type: Option<i32>,
+    pub local_variables: Option<HashMap<String, String>>,
+    pub prev_buffer: Option<String>,
+    pub next_buffer: Option<String>,
+}
+
+impl BufferChange {
+    pub fn from_hdata_row(pointer: String,
+                          row: &HashMap<String, WeechatData>)
+                          -> Result<BufferChange, WeechatParseError> {
+        Ok(BufferChange {
+            pointer: pointer,
+            number: try!(get_int(row, "number")),
+            full_name: try!(get_string(row, "full_name")),
+            short_name: try!(optional(row, "short_name", get_string)),
+            title: try!(optional(row, "title", get_string)),
+            nicklist: try!(optional(row, "nicklist", get_flag)),
+            buffer_type: try!(optional(row, "type", get_int)),
+            local_variables: try!(optional(row, "local_variables", get_string_hashtable)),
+            prev_buffer: try!(optional(row, "prev_buffer", get_pointer)),
+            next_buffer: try!(optional(row, "next_buffer", get_pointer)),
+        })
+    }
+
+    pub fn all_from(message: &WeechatMessage) -> Result<Vec<BufferChange>, WeechatParseError> {
+        let (pointers, rows) = try!(get_hdata(message, "buffer"));
+        let mut acc = Vec::with_capacity(rows.len());
+        for (index, row) in rows.iter().enumerate() {
+            let pointer = try!(row_pointer(pointers, rows.len(), index, 0));
+            acc.push(try!(BufferChange::from_hdata_row(pointer, row)));
+        }
+        Ok(acc)
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum NicklistDiff {
+    /// `^`: the following items belong to this parent group.
+    Parent,
+    /// `+`: the item was added.
+    Added,
+    /// `-`: the item was removed.
+    Removed,
+    /// `*`: the item was changed.
+    Changed,
+}
+
+/// A nick or group from `_nicklist` or `_nicklist_diff`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct NicklistItem {
+    pub buffer: String,
+    pub pointer: String,
+    /// Only set for items from `_nicklist_diff`.
+    pub diff: Option<NicklistDiff>,
+    pub group: bool,
+    pub visible: bool,
+    pub level: i32,
+    pub name: String,
+    pub color: String,
+    pub prefix: String,
+    pub prefix_color: String,
+}
+
+impl NicklistItem {
+    pub fn from_hdata_row(buffer: String,
+                          pointer: String,
+                          row: &HashMap<String, WeechatData>)
+                          -> Result<NicklistItem, WeechatParseError> {
+        let diff = match try!(optional(row, "_diff", get_char)) {
+            None => None,
+            Some('^') => Some(NicklistDiff::Parent),
+            Some('+') => Some(NicklistDiff::Added),
+            Some('-') => Some(NicklistDiff::Removed),
+            Some('*') => Some(NicklistDiff::Changed),
+            Some(other) => {
+                fail!((UnexpectedType,
+                       "Unknown nicklist diff marker",
+                       format!("found {:?}", other)))
+            }
+        };
+        Ok(NicklistItem {
+            buffer: buffer,
+            pointer: pointer,
+            diff: diff,
+            group: try!(get_flag(row, "group")),
+            visible: try!(get_flag(row, "visible")),
+            level: try!(get_int(row, "level")),
+            name: try!(get_string(row, "name")),
+            color: try!(get_string(row, "color")),
+            prefix: try!(get_string(row, "prefix")),
+            prefix_color: try!(get_string(row, "prefix_color")),
+        })
+    }
+
+    pub fn all_from(message: &WeechatMessage) -> Result<Vec<NicklistItem>, WeechatParseError> {
+        let (pointers, rows) = try!(get_hdata(message, "nicklist_item"));
+        let mut acc = Vec::with_capacity(rows.len());
+        for (index, row) in rows.iter().enumerate() {
+            let buffer = try!(row_pointer(pointers, rows.len(), index, 0));
+            let pointer = try!(row_pointer(pointers, rows.len(), index, 1));
+            acc.push(try!(NicklistItem::from_hdata_row(buffer, pointer, row)));
+        }
+        Ok(acc)
+    }
+}
+
+/// What kind of word `completion` completed.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CompletionContext {
+    /// Nothing could be completed.
+    Null,
+    /// A command name.
+    Command,
+    /// An argument of a command.
+    CommandArg,
+    /// A word in a message, usually a nick.
+    Auto,
+}
+
+/// The reply to `completion`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Completion {
+    pub context: CompletionContext,
+    /// The word being completed.
+    pub base_word: String,
+    /// Where the word starts and ends in the input, the part candidates replace.
+    pub pos_start: i32,
+    pub pos_end: i32,
+    /// Whether a space should follow the completed word.
+    pub add_space: bool,
+    pub list: Vec<String>,
+}
+
+impl Completion {
+    pub fn from_hdata_row(row: &HashMap<String, WeechatData>) -> Result<Completion, WeechatParseError> {
+        let context = match &try!(get_string(row, "context"))[..] {
+            "null" => CompletionContext::Null,
+            "command" => CompletionContext::Command,
+            "command_arg" => CompletionContext::CommandArg,
+            "auto" => CompletionContext::Auto,
+            other => {
+                fail!((UnexpectedType, "Unknown completion context", other.to_owned()))
+            }
+        };
+        Ok(Completion {
+            context: context,
+            base_word: try!(get_string(row, "base_word")),
+            pos_start: try!(get_int(row, "pos_start")),
+            pos_end: try!(get_int(row, "pos_end")),
+            add_space: try!(get_flag(row, "add_space")),
+            list: try!(get_string_array(row, "list")),
+        })
+    }
+
+    /// The completion in `message`, or `None` if the relay couldn't complete, in which case it
+    /// sends an hdata without rows and often without a path.
+    pub fn from_message(message: &WeechatMessage)
+                        -> Result<Option<Completion>, WeechatParseError> {
+        if is_empty_hdata(message) {
+            return Ok(None);
+        }
+        let rows = try!(get_hdata_rows(message, "completion"));
+        match rows.first() {
+            Some(row) => Ok(Some(try!(Completion::from_hdata_row(row)))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A message from the relay, decoded according to its id. Replies to commands and events this
+/// crate doesn't know about are kept as `Unknown`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RelayEvent {
+    BufferOpened(Vec<BufferChange>),
+    BufferTypeChanged(Vec<BufferChange>),
+    BufferMoved(Vec<BufferChange>),
+    BufferMerged(Vec<BufferChange>),
+    BufferUnmerged(Vec<BufferChange>),
+    BufferHidden(Vec<BufferChange>),
+    BufferUnhidden(Vec<BufferChange>),
+    BufferRenamed(Vec<BufferChange>),
+    BufferTitleChanged(Vec<BufferChange>),
+    BufferLocalvarAdded(Vec<BufferChange>),
+    BufferLocalvarChanged(Vec<BufferChange>),
+    BufferLocalvarRemoved(Vec<BufferChange>),
+    BufferCleared(Vec<BufferChange>),
+    BufferClosing(Vec<BufferChange>),
+    LineAdded(Vec<LineAdded>),
+    Nicklist(Vec<NicklistItem>),
+    NicklistDiff(Vec<NicklistItem>),
+    Pong(String),
+    Upgrade,
+    UpgradeEnded,
+    Unknown(WeechatMessage),
+}
+
+impl RelayEvent {
+    pub fn from_message(message: WeechatMessage) -> Result<RelayEvent, WeechatParseError> {
+        let event = match message.id.as_ref() {
+            "_buffer_opened" => RelayEvent::BufferOpened(try!(BufferChange::all_from(&message))),
+            "_buffer_type_changed" => {
+                RelayEvent::BufferTypeChanged(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_moved" => RelayEvent::BufferMoved(try!(BufferChange::all_from(&message))),
+            "_buffer_merged" => RelayEvent::BufferMerged(try!(BufferChange::all_from(&message))),
+            "_buffer_unmerged" => {
+                RelayEvent::BufferUnmerged(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_hidden" => RelayEvent::BufferHidden(try!(BufferChange::all_from(&message))),
+            "_buffer_unhidden" => {
+                RelayEvent::BufferUnhidden(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_renamed" => RelayEvent::BufferRenamed(try!(BufferChange::all_from(&message))),
+            "_buffer_title_changed" => {
+                RelayEvent::BufferTitleChanged(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_localvar_added" => {
+                RelayEvent::BufferLocalvarAdded(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_localvar_changed" => {
+                RelayEvent::BufferLocalvarChanged(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_localvar_removed" => {
+                RelayEvent::BufferLocalvarRemoved(try!(BufferChange::all_from(&message)))
+            }
+            "_buffer_cleared" => RelayEvent::BufferCleared(try!(BufferChange::all_from(&message))),
+            "_buffer_closing" => RelayEvent::BufferClosing(try!(BufferChange::all_from(&message))),
+            "_buffer_line_added" => RelayEvent::LineAdded(try!(LineAdded::all_from(&message))),
+            "_nicklist" => RelayEvent::Nicklist(try!(NicklistItem::all_from(&message))),
+            "_nicklist_diff" => RelayEvent::NicklistDiff(try!(NicklistItem::all_from(&message))),
+            "_pong" => {
+                match message.data.get(0) {
+                    Some(&WeechatData::String(ref value)) => RelayEvent::Pong(value.clone()),
+                    Some(&WeechatData::StringNull) | None => RelayEvent::Pong("".to_owned()),
+                    Some(other) => return Err(unexpected_type("_pong", "str", other)),
+                }
+            }
+            "_upgrade" => RelayEvent::Upgrade,
+            "_upgrade_ended" => RelayEvent::UpgradeEnded,
+            _ => RelayEvent::Unknown(message),
+        };
+        Ok(event)
+    }
+}
+
+impl TryFrom<WeechatMessage> for RelayEvent {
+    type Error = WeechatParseError;
+
+    fn try_from(message: WeechatMessage) -> Result<RelayEvent, WeechatParseError> {
+        RelayEvent::from_message(message)
+    }
+}
+
+/// The rows of the hdata at the start of `message`, checking that its path ends in `name`.
+pub fn get_hdata_rows<'a>(message: &'a WeechatMessage,
+                          name: &str)
+                          -> Result<&'a Vec<HashMap<String, WeechatData>>, WeechatParseError> {
+    let (_, rows) = try!(get_hdata(message, name));
+    Ok(rows)
+}
+
+/// Whether `message` is an hdata without rows. The relay sends those when nothing matched, with
+/// whatever path, even a null one.
+fn is_empty_hdata(message: &WeechatMessage) -> bool {
+    match message.data.get(0) {
+        Some(&WeechatData::Hdata(_, _, ref rows)) => rows.is_empty(),
+        _ => false,
+    }
+}
+
+fn get_hdata<'a>(message: &'a WeechatMessage,
+                 name: &str)
+                 -> Result<(&'a Vec<WeechatData>, &'a Vec<HashMap<String, WeechatData>>),
+                           WeechatParseError> {
+    match message.data.get(0) {
+        Some(&WeechatData::Hdata(ref path, ref pointers, ref rows)) => {
+            if path.split('/').last() != Some(name) {
+                fail!((UnexpectedType,
+                       "Hdata has an unexpected path",
+                       format!("expected {} but found {}", name, path)))
+            }
+            Ok((pointers, rows))
+        }
+        Some(other) => {
+            fail!((UnexpectedType,
+                   "Message doesn't start with hdata",
+                   format!("found {:?} in {}", other, message.id)))
+        }
+        None => fail!((MissingField, "Message has no data", message.id.clone())),
+    }
+}
+
+/// The pointer at `position` in the path of row `index`.
+fn row_pointer(pointers: &[WeechatData],
+               row_count: usize,
+               index: usize,
+               position: usize)
+               -> Result<String, WeechatParseError> {
+    let per_row = if row_count == 0 { 0 } else { pointers.len() / row_count };
+    if position >= per_row {
+        fail!((MissingField,
+               "Hdata path is too short",
+               format!("wanted pointer {} but rows only have {}", position, per_row)))
+    }
+    match pointers.get(index * per_row + position) {
+        Some(&WeechatData::Pointer(ref value)) => Ok(value.clone()),
+        Some(other) => Err(unexpected_type("hdata path", "ptr", other)),
+        None => fail!((MissingField, "Hdata is missing pointers", format!("row {}", index))),
+    }
+}
+
+/// Run `getter` if `key` is in `row`, for fields only sent with some events.
+fn optional<T, F>(row: &HashMap<String, WeechatData>,
+                  key: &str,
+                  getter: F)
+                  -> Result<Option<T>, WeechatParseError>
+    where F: Fn(&HashMap<String, WeechatData>, &str) -> Result<T, WeechatParseError>
+{
+    if row.contains_key(key) {
+        Ok(Some(try!(getter(row, key))))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_field<'a>(row: &'a HashMap<String, WeechatData>,
+                 key: &str)
+                 -> Result<&'a WeechatData, WeechatParseError> {
+    match row.get(key) {
+        Some(value) => Ok(value),
+        None => fail!((MissingField, "Hdata row is missing a field", key.to_owned())),
+    }
+}
+
+fn unexpected_type(key: &str, expected: &str, found: &WeechatData) -> WeechatParseError {
+    WeechatParseError::from((UnexpectedType,
+                             "Field has an unexpected type",
+                             format!("expected {} for {} but found {:?}", expected, key, found)))
+}
+
+fn get_pointer(row: &HashMap<String, WeechatData>, key: &str) -> Result<String, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Pointer(ref value) => Ok(value.clone()),
+        ref other => Err(unexpected_type(key, "ptr", other)),
+    }
+}
+
+fn get_int(row: &HashMap<String, WeechatData>, key: &str) -> Result<i32, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Int(value) => Ok(value),
+        ref other => Err(unexpected_type(key, "int", other)),
+    }
+}
+
+fn get_char(row: &HashMap<String, WeechatData>, key: &str) -> Result<char, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Char(value) => Ok(value),
+        ref other => Err(unexpected_type(key, "chr", other)),
+    }
+}
+
+fn get_time(row: &HashMap<String, WeechatData>, key: &str) -> Result<i64, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Time(ref value) => Ok(try!(value.parse())),
+        ref other => Err(unexpected_type(key, "tim", other)),
+    }
+}
+
+fn get_flag(row: &HashMap<String, WeechatData>, key: &str) -> Result<bool, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Char(value) => Ok(value != '\u{0}'),
+        WeechatData::Int(value) => Ok(value != 0),
+        ref other => Err(unexpected_type(key, "chr or int", other)),
+    }
+}
+
+fn get_string(row: &HashMap<String, WeechatData>, key: &str) -> Result<String, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::String(ref value) => Ok(value.clone()),
+        WeechatData::StringNull => Ok("".to_owned()),
+        ref other => Err(unexpected_type(key, "str", other)),
+    }
+}
+
+fn get_string_array(row: &HashMap<String, WeechatData>,
+                    key: &str)
+                    -> Result<Vec<String>, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Array(ref values) => {
+            let mut acc = Vec::with_capacity(values.len());
+            for value in values {
+                match *value {
+                    WeechatData::String(ref string) => acc.push(string.clone()),
+                    WeechatData::StringNull => acc.push("".to_owned()),
+                    ref other => return Err(unexpected_type(key, "arr of str", other)),
+                }
+            }
+            Ok(acc)
+        }
+        ref other => Err(unexpected_type(key, "arr", other)),
+    }
+}
+
+fn get_string_hashtable(row: &HashMap<String, WeechatData>,
+                        key: &str)
+                        -> Result<HashMap<String, String>, WeechatParseError> {
+    match *try!(get_field(row, key)) {
+        WeechatData::Hashtable(_, _, ref entries) => {
+            let mut acc = HashMap::new();
+            for &(ref entry_key, ref entry_value) in entries {
+                match (entry_key, entry_value) {
+                    (&WeechatData::String(ref k), &WeechatData::String(ref v)) => {
+                        acc.insert(k.clone(), v.clone());
+                    }
+                    (&WeechatData::String(ref k), &WeechatData::StringNull) => {
+                        acc.insert(k.clone(), "".to_owned());
+                    }
+                    (other, _) => return Err(unexpected_type(key, "htb of str", other)),
+                }
+            }
+            Ok(acc)
+        }
+        ref other => Err(unexpected_type(key, "htb", other)),
+    }
+}
+
+#[cfg(test)]
+fn line_row() -> HashMap<String, WeechatData> {
+    let mut row = HashMap::new();
+    row.insert("buffer".to_owned(), WeechatData::Pointer("0x1234".to_owned()));
+    row.insert("date".to_owned(), WeechatData::Time("1439651878".to_owned()));
+    row.insert("date_printed".to_owned(), WeechatData::Time("1439651879".to_owned()));
+    row.insert("displayed".to_owned(), WeechatData::Char('\u{1}'));
+    row.insert("highlight".to_owned(), WeechatData::Char('\u{0}'));
+    row.insert("tags_array".to_owned(),
+               WeechatData::Array(vec![WeechatData::String("irc_privmsg".to_owned())]));
+    row.insert("prefix".to_owned(), WeechatData::String("Wraithan".to_owned()));
+    row.insert("message".to_owned(), WeechatData::String("Hey".to_owned()));
+    row
+}
+
+#[test]
+fn test_line_added_from_message() {
+    let message = WeechatMessage {
+        id: "_buffer_line_added".to_owned(),
+        data: vec![WeechatData::Hdata("line_data".to_owned(),
+                                      vec![WeechatData::Pointer("0xabc".to_owned())],
+                                      vec![line_row()])],
+    };
+    let line = LineAdded::try_from(&message).unwrap();
+    assert_eq!(line,
+               LineAdded {
+                   buffer: "0x1234".to_owned(),
+                   date: 1439651878,
+                   date_printed: 1439651879,
+                   displayed: true,
+                   highlight: false,
+                   tags: vec!["irc_privmsg".to_owned()],
+                   prefix: "Wraithan".to_owned(),
+                   message: "Hey".to_owned(),
+               });
+    assert_eq!(LineAdded::all_from(&message).unwrap(), vec![line]);
+}
+
+#[test]
+fn test_line_added_errors() {
+    let mut row = line_row();
+    row.remove("highlight");
+    let error = LineAdded::from_hdata_row(&row).unwrap_err();
+    assert_eq!(error.kind(), MissingField);
+    assert_eq!(format!("{}", error), "Hdata row is missing a field: highlight");
+
+    let mut row = line_row();
+    row.insert("message".to_owned(), WeechatData::Int(1));
+    assert_eq!(LineAdded::from_hdata_row(&row).unwrap_err().kind(), UnexpectedType);
+
+    let mut row = line_row();
+    row.insert("displayed".to_owned(), WeechatData::String("1".to_owned()));
+    let error = LineAdded::from_hdata_row(&row).unwrap_err();
+    assert_eq!(error.kind(), UnexpectedType);
+    assert_eq!(format!("{}", error),
+               "Field has an unexpected type: expected chr or int for displayed but found \
+                String(\"1\")");
+
+    let empty = WeechatMessage {
+        id: "_buffer_line_added".to_owned(),
+        data: vec![],
+    };
+    assert_eq!(LineAdded::try_from(&empty).unwrap_err().kind(), MissingField);
+
+    let wrong_hdata = WeechatMessage {
+        id: "_buffer_line_added".to_owned(),
+        data: vec![WeechatData::Hdata("buffer".to_owned(), vec![], vec![])],
+    };
+    assert_eq!(LineAdded::try_from(&wrong_hdata).unwrap_err().kind(), UnexpectedType);
+
+    let no_rows = WeechatMessage {
+        id: "_buffer_line_added".to_owned(),
+        data: vec![WeechatData::Hdata("line_data".to_owned(), vec![], vec![])],
+    };
+    assert_eq!(LineAdded::try_from(&no_rows).unwrap_err().kind(), MissingField);
+    assert_eq!(LineAdded::all_from(&no_rows).unwrap(), vec![]);
+    let no_path = WeechatMessage {
+        id: "r1".to_owned(),
+        data: vec![WeechatData::Hdata("".to_owned(), vec![], vec![])],
+    };
+    assert_eq!(LineAdded::all_from(&no_path).unwrap(), vec![]);
+}
+
+#[cfg(test)]
+fn hdata_message(id: &str,
+                 path: &str,
+                 pointers: Vec<&str>,
+                 rows: Vec<Vec<(&str, WeechatData)>>)
+                 -> WeechatMessage {
+    WeechatMessage {
+        id: id.to_owned(),
+        data: vec![WeechatData::Hdata(path.to_owned(),
+                                      pointers.into_iter()
+                                              .map(|p| WeechatData::Pointer(p.to_owned()))
+                                              .collect(),
+                                      rows.into_iter()
+                                          .map(|row| {
+                                              row.into_iter()
+                                                 .map(|(k, v)| (k.to_owned(), v))
+                                                 .collect()
+                                          })
+                                          .collect())],
+    }
+}
+
+#[test]
+fn test_event_buffer_opened() {
+    let local_variables = WeechatData::Hashtable("str".to_owned(),
+                                                 "str".to_owned(),
+                                                 vec![(WeechatData::String("plugin".to_owned()),
+                                                       WeechatData::String("irc".to_owned()))]);
+    let message = hdata_message("_buffer_opened",
+                                "buffer",
+                                vec!["0x35a8a60"],
+                                vec![vec![("number", WeechatData::Int(3)),
+                                          ("full_name", WeechatData::String("irc.f.#w".to_owned())),
+                                          ("short_name", WeechatData::String("#w".to_owned())),
+                                          ("nicklist", WeechatData::Int(1)),
+                                          ("title", WeechatData::StringNull),
+                                          ("local_variables", local_variables),
+                                          ("prev_buffer", WeechatData::Pointer("0x1".to_owned())),
+                                          ("next_buffer", WeechatData::Pointer("0x0".to_owned()))]]);
+    let mut expected_variables = HashMap::new();
+    expected_variables.insert("plugin".to_owned(), "irc".to_owned());
+    assert_eq!(RelayEvent::from_message(message).unwrap(),
+               RelayEvent::BufferOpened(vec![BufferChange {
+                                                 pointer: "0x35a8a60".to_owned(),
+                                                 number: 3,
+                                                 full_name: "irc.f.#w".to_owned(),
+                                                 short_name: Some("#w".to_owned()),
+                                                 title: Some("".to_owned()),
+                                                 nicklist: Some(true),
+                                                 buffer_type: None,
+                                                 local_variables: Some(expected_variables),
+                                                 prev_buffer: Some("0x1".to_owned()),
+                                                 next_buffer: Some("0x0".to_owned()),
+                                             }]));
+}
+
+#[test]
+fn test_event_buffer_closing() {
+    let message = hdata_message("_buffer_closing",
+                                "buffer",
+                                vec!["0x35a8a60"],
+                                vec![vec![("number", WeechatData::Int(3)),
+                                          ("full_name", WeechatData::String("irc.f.#w".to_owned()))]]);
+    match RelayEvent::from_message(message).unwrap() {
+        RelayEvent::BufferClosing(buffers) => {
+            assert_eq!(buffers.len(), 1);
+            assert_eq!(buffers[0].pointer, "0x35a8a60");
+            assert_eq!(buffers[0].full_name, "irc.f.#w");
+            assert_eq!(buffers[0].title, None);
+        }
+        other => panic!("expected BufferClosing, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_event_nicklist_diff() {
+    let item = |diff: char, group: char, name: &str| {
+        vec![("_diff", WeechatData::Char(diff)),
+             ("group", WeechatData::Char(group)),
+             ("visible", WeechatData::Char('\u{1}')),
+             ("level", WeechatData::Int(0)),
+             ("name", WeechatData::String(name.to_owned())),
+             ("color", WeechatData::String("cyan".to_owned())),
+             ("prefix", WeechatData::String("@".to_owned())),
+             ("prefix_color", WeechatData::String("lightgreen".to_owned()))]
+    };
+    let message = hdata_message("_nicklist_diff",
+                                "buffer/nicklist_item",
+                                vec!["0xb1", "0x11", "0xb1", "0x12"],
+                                vec![item('^', '\u{1}', "root"), item('+', '\u{0}', "Wraithan")]);
+    match RelayEvent::from_message(message).unwrap() {
+        RelayEvent::NicklistDiff(items) => {
+            assert_eq!(items.len(), 2);
+            assert_eq!(items[0].diff, Some(NicklistDiff::Parent));
+            assert!(items[0].group);
+            assert_eq!(items[1],
+                       NicklistItem {
+                           buffer: "0xb1".to_owned(),
+                           pointer: "0x12".to_owned(),
+                           diff: Some(NicklistDiff::Added),
+                           group: false,
+                           visible: true,
+                           level: 0,
+                           name: "Wraithan".to_owned(),
+                           color: "cyan".to_owned(),
+                           prefix: "@".to_owned(),
+                           prefix_color: "lightgreen".to_owned(),
+                       });
+        }
+        other => panic!("expected NicklistDiff, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_completion_from_message() {
+    let list = WeechatData::Array(vec![WeechatData::String("Wraithan".to_owned()),
+                                       WeechatData::String("WeeChat".to_owned())]);
+    let message = hdata_message("r1",
+                                "completion",
+                                vec!["0x55d0ccc4b7c0"],
+                                vec![vec![("context", WeechatData::String("auto".to_owned())),
+                                          ("base_word", WeechatData::String("W".to_owned())),
+                                          ("pos_start", WeechatData::Int(0)),
+                                          ("pos_end", WeechatData::Int(0)),
+                                          ("add_space", WeechatData::Int(0)),
+                                          ("list", list)]]);
+    assert_eq!(Completion::from_message(&message).unwrap(),
+               Some(Completion {
+                   context: CompletionContext::Auto,
+                   base_word: "W".to_owned(),
+                   pos_start: 0,
+                   pos_end: 0,
+                   add_space: false,
+                   list: vec!["Wraithan".to_owned(), "WeeChat".to_owned()],
+               }));
+
+    let failed = hdata_message("r1", "completion", vec![], vec![]);
+    assert_eq!(Completion::from_message(&failed).unwrap(), None);
+    // What the relay really sends when it can't complete: an hdata with a null path and keys,
+    // or an empty one, and no rows.
+    for &empty in &[[255, 255, 255, 255], [0, 0, 0, 0]] {
+        let mut raw = vec![0, 0, 0, 26, 0, 0, 0, 0, 2, 114, 49, 104, 100, 97];
+        raw.extend_from_slice(&empty);
+        raw.extend_from_slice(&empty);
+        raw.extend_from_slice(&[0, 0, 0, 0]);
+        let failed = WeechatMessage::from_raw_message(&raw).unwrap();
+        assert_eq!(Completion::from_message(&failed).unwrap(), None);
+    }
+    let mut row = HashMap::new();
+    row.insert("context".to_owned(), WeechatData::String("nick".to_owned()));
+    assert_eq!(Completion::from_hdata_row(&row).unwrap_err().kind(), UnexpectedType);
+}
+
+#[test]
+fn test_event_simple_ids() {
+    let pong = WeechatMessage {
+        id: "_pong".to_owned(),
+        data: vec![WeechatData::String("abc".to_owned())],
+    };
+    assert_eq!(RelayEvent::from_message(pong).unwrap(),
+               RelayEvent::Pong("abc".to_owned()));
+
+    let upgrade = WeechatMessage {
+        id: "_upgrade".to_owned(),
+        data: vec![],
+    };
+    assert_eq!(RelayEvent::try_from(upgrade).unwrap(), RelayEvent::Upgrade);
+
+    let reply = WeechatMessage {
+        id: "version".to_owned(),
+        data: vec![WeechatData::Info("version".to_owned(), Some("1.2".to_owned()))],
+    };
+    assert_eq!(RelayEvent::from_message(reply.clone()).unwrap(),
+               RelayEvent::Unknown(reply));
+}
+
+#[test]
+fn test_event_bad_payload() {
+    let message = WeechatMessage {
+        id: "_buffer_renamed".to_owned(),
+        data: vec![WeechatData::Int(1)],
+    };
+    assert_eq!(RelayEvent::from_message(message).unwrap_err().kind(), UnexpectedType);
+}
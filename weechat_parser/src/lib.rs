@@ -1,10 +1,14 @@
 extern crate byteorder;
 extern crate flate2;
+extern crate zstd;
 
 #[macro_use]
 pub mod errors;
+pub mod encoder;
+pub mod events;
 
 use std::char;
+use std::cmp;
 use std::io::Cursor;
 use std::io::prelude::*;
 use std::string::String;
@@ -14,7 +18,7 @@ use std::thread;
 use byteorder::{ReadBytesExt, BigEndian};
 use flate2::read::ZlibDecoder;
 use errors::WeechatParseError;
-use errors::ErrorKind::{MalformedBinaryParse, UnknownType};
+use errors::ErrorKind::{MalformedBinaryParse, UnknownCompression, UnknownType};
 
 macro_rules! println_stderr(
     ($($arg:tt)*) => (
@@ -24,7 +28,7 @@ macro_rules! println_stderr(
     )
 );
 
-#[derive(Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct WeechatMessage {
     pub id: String,
     pub data: Vec<WeechatData>,
@@ -43,7 +47,20 @@ pub enum WeechatData {
     Pointer(String),
     Time(String),
     Array(Vec<WeechatData>),
+    Hashtable(String, String, Vec<(WeechatData, WeechatData)>),
     Hdata(String, Vec<WeechatData>, Vec<HashMap<String, WeechatData>>),
+    /// Name and value. A null name is read as "".
+    Info(String, Option<String>),
+    /// Name and items of variable names and values. Null names are read as "".
+    Infolist(String, Vec<Vec<(String, WeechatData)>>),
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Compression {
+    None,
+    Zlib,
+    Zstd,
+    Unknown(u8),
 }
 
 impl WeechatMessage {
@@ -55,6 +72,10 @@ impl WeechatMessage {
         let data = try!(parse_data(&raw_data[len..], length));
         Ok(WeechatMessage { id: name, data: data })
     }
+
+    pub fn to_bytes(&self, compression: Compression) -> Result<Vec<u8>, WeechatParseError> {
+        encoder::encode(self, compression)
+    }
 }
 
 pub fn new() -> (Sender<Vec<u8>>, Receiver<Result<WeechatMessage, WeechatParseError>>) {
@@ -158,13 +179,22 @@ fn parse_element(element_type: &str,
             let (len, value) = try!(read_time(&buffer));
             Ok((len, WeechatData::Time(value)))
         }
-        // "htb" => break,
+        "htb" => {
+            let (len, key_type, value_type, value) = try!(read_hashtable(&buffer));
+            Ok((len, WeechatData::Hashtable(key_type, value_type, value)))
+        }
         "hda" => {
             let (len, name, pointers, value) = try!(read_hdata(&buffer));
             Ok((len, WeechatData::Hdata(name, pointers, value)))
         }
-        // "inf" => break,
-        // "inl" => break,
+        "inf" => {
+            let (len, name, value) = try!(read_info(&buffer));
+            Ok((len, WeechatData::Info(name, value)))
+        }
+        "inl" => {
+            let (len, name, items) = try!(read_infolist(&buffer));
+            Ok((len, WeechatData::Infolist(name, items)))
+        }
         "arr" => {
             let (len, value) = try!(read_array(&buffer));
             Ok((len, WeechatData::Array(value)))
@@ -194,6 +224,15 @@ fn read_i32(buffer: &[u8]) -> Result<i32, WeechatParseError> {
     Ok(try!(datum.read_i32::<BigEndian>()))
 }
 
+/// A count of elements, which the relay never sends negative.
+fn read_count(buffer: &[u8]) -> Result<usize, WeechatParseError> {
+    let count = try!(read_i32(buffer));
+    if count < 0 {
+        fail!((MalformedBinaryParse, "Got a negative count", count.to_string()));
+    }
+    Ok(count as usize)
+}
+
 fn read_long(buffer: &[u8]) -> Result<(usize, i64), WeechatParseError> {
     let (end, value) = try!(read_string_8bit_length(&buffer));
     let long = try!(i64::from_str_radix(&value, 10));
@@ -212,6 +251,26 @@ fn read_time(buffer: &[u8]) -> Result<(usize, String), WeechatParseError> {
     read_string_8bit_length(&buffer)
 }
 
+fn read_hashtable(buffer: &[u8])
+                  -> Result<(usize, String, String, Vec<(WeechatData, WeechatData)>),
+                            WeechatParseError> {
+    let key_type = get_element_type(&buffer);
+    let value_type = get_element_type(&buffer[3..]);
+    let mut position = 6;
+    let count = try!(read_count(&buffer[position..]));
+    position += 4;
+    // Every entry takes at least a byte, a bogus count can't make us allocate more than that.
+    let mut acc = Vec::with_capacity(cmp::min(count, buffer.len()));
+    for _ in 0..count {
+        let (key_len, key) = try!(parse_element(&key_type, &buffer[position..]));
+        position += key_len;
+        let (value_len, value) = try!(parse_element(&value_type, &buffer[position..]));
+        position += value_len;
+        acc.push((key, value));
+    }
+    Ok((position, key_type, value_type, acc))
+}
+
 fn read_hdata(buffer: &[u8])
               -> Result<(usize,
                          String,
@@ -219,23 +278,25 @@ fn read_hdata(buffer: &[u8])
                          Vec<HashMap<String, WeechatData>>),
                         WeechatParseError> {
     let mut position = 0;
+    // Hdata without rows, sent when nothing matched, can have null path and keys.
     let (name_len, name_raw) = try!(read_string_32bit_length(&buffer));
-    let name = name_raw.unwrap();
+    let name = name_raw.unwrap_or_default();
     position += name_len;
     let pointer_count = name.match_indices('/').count() + 1;
     let (keys_len, keys_raw) = try!(read_string_32bit_length(&buffer[position..]));
     position += keys_len;
-    let keys_owned = keys_raw.unwrap();
-    let row_count = try!(read_i32(&buffer[position..])) as usize;
+    let keys_owned = keys_raw.unwrap_or_default();
+    let row_count = try!(read_count(&buffer[position..]));
     position += 4;
 
     let mut keys = vec![];
-    for chunk in keys_owned.split(',') {
+    for chunk in keys_owned.split(',').filter(|chunk| !chunk.is_empty()) {
         let key: Vec<&str> = chunk.split(':').collect();
         keys.push((key[0].to_owned(), key[1].to_owned()));
     }
-    let mut pointers = Vec::with_capacity(pointer_count * row_count);
-    let mut acc = Vec::with_capacity(row_count);
+    let pointer_total = pointer_count.checked_mul(row_count).unwrap_or(buffer.len());
+    let mut pointers = Vec::with_capacity(cmp::min(pointer_total, buffer.len()));
+    let mut acc = Vec::with_capacity(cmp::min(row_count, buffer.len()));
     for _ in 0..row_count {
         for _ in 0..pointer_count {
             let (ptr_len, ptr_value) = try!(read_pointer(&buffer[position..]));
@@ -254,6 +315,38 @@ fn read_hdata(buffer: &[u8])
     Ok((position, name, pointers, acc))
 }
 
+fn read_info(buffer: &[u8]) -> Result<(usize, String, Option<String>), WeechatParseError> {
+    let (name_len, name_raw) = try!(read_string_32bit_length(&buffer));
+    let (value_len, value) = try!(read_string_32bit_length(&buffer[name_len..]));
+    Ok((name_len + value_len, name_raw.unwrap_or_default(), value))
+}
+
+fn read_infolist(buffer: &[u8])
+                 -> Result<(usize, String, Vec<Vec<(String, WeechatData)>>), WeechatParseError> {
+    let mut position = 0;
+    let (name_len, name_raw) = try!(read_string_32bit_length(&buffer));
+    position += name_len;
+    let item_count = try!(read_count(&buffer[position..]));
+    position += 4;
+    let mut acc = Vec::with_capacity(cmp::min(item_count, buffer.len()));
+    for _ in 0..item_count {
+        let variable_count = try!(read_count(&buffer[position..]));
+        position += 4;
+        let mut item = Vec::with_capacity(cmp::min(variable_count, buffer.len()));
+        for _ in 0..variable_count {
+            let (var_name_len, var_name) = try!(read_string_32bit_length(&buffer[position..]));
+            position += var_name_len;
+            let var_type = get_element_type(&buffer[position..]);
+            position += 3;
+            let (len, value) = try!(parse_element(&var_type, &buffer[position..]));
+            position += len;
+            item.push((var_name.unwrap_or_default(), value));
+        }
+        acc.push(item);
+    }
+    Ok((position, name_raw.unwrap_or_default(), acc))
+}
+
 fn read_string_8bit_length(buffer: &[u8]) -> Result<(usize, String), WeechatParseError> {
     let length = try!(read_u8(&buffer)) as usize;
     let end = length + 1;
@@ -280,31 +373,14 @@ fn read_string_32bit_length(buffer: &[u8]) -> Result<(usize, Option<String>), We
 fn read_array(buffer: &[u8]) -> Result<(usize, Vec<WeechatData>), WeechatParseError> {
     let array_type = get_element_type(&buffer);
     let mut position = 3;
-    let count = try!(read_i32(&buffer[position..]));
+    let count = try!(read_count(&buffer[position..]));
     position += 4;
-    let mut acc = Vec::<WeechatData>::with_capacity(count as usize);
-    match array_type.as_ref() {
-        "str" => {
-            for _ in 0..count {
-                let (len, value) = try!(read_string_32bit_length(&buffer[position..]));
-                match value {
-                    Some(string) => acc.push(WeechatData::String(string)),
-                    None => acc.push(WeechatData::StringNull),
-                }
-                position += len;
-            }
-        }
-        "int" => {
-            for _ in 0..count {
-                let value = try!(read_i32(&buffer[position..]));
-                acc.push(WeechatData::Int(value));
-                position += 4;
-            }
-        }
-        _ => fail!((UnknownType,
-                    "array isn't implemented for type",
-                    format!("found array type {:?}", array_type))),
-    };
+    let mut acc = Vec::<WeechatData>::with_capacity(cmp::min(count, buffer.len()));
+    for _ in 0..count {
+        let (len, value) = try!(parse_element(&array_type, &buffer[position..]));
+        acc.push(value);
+        position += len;
+    }
     Ok((position, acc))
 }
 
@@ -312,9 +388,14 @@ pub fn get_length(buffer: &[u8]) -> Result<u32, WeechatParseError> {
     read_u32(buffer)
 }
 
-pub fn get_compression(buffer: &[u8]) -> Result<bool, WeechatParseError> {
+pub fn get_compression(buffer: &[u8]) -> Result<Compression, WeechatParseError> {
     if let Some(flag) = buffer.get(4) {
-        Ok(flag == &1)
+        match *flag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zlib),
+            2 => Ok(Compression::Zstd),
+            other => Ok(Compression::Unknown(other)),
+        }
     } else {
         fail!((MalformedBinaryParse, "Could not find compression flag"))
     }
@@ -325,11 +406,28 @@ fn get_message_type(buffer: &[u8]) -> Result<(usize, Option<String>), WeechatPar
 }
 
 fn get_raw_data(buffer: &[u8]) -> Result<Vec<u8>, WeechatParseError> {
+    let compression = try!(get_compression(&buffer));
     let mut datum = Cursor::new(buffer);
     datum.set_position(5);
-    let mut decoder = ZlibDecoder::new(datum);
     let mut result = Vec::<u8>::new();
-    try!(decoder.read_to_end(&mut result));
+    match compression {
+        Compression::None => {
+            try!(datum.read_to_end(&mut result));
+        }
+        Compression::Zlib => {
+            let mut decoder = ZlibDecoder::new(datum);
+            try!(decoder.read_to_end(&mut result));
+        }
+        Compression::Zstd => {
+            let mut decoder = try!(zstd::stream::read::Decoder::new(datum));
+            try!(decoder.read_to_end(&mut result));
+        }
+        Compression::Unknown(flag) => {
+            fail!((UnknownCompression,
+                   "Got unfamiliar compression flag",
+                   format!("found compression flag {}", flag)))
+        }
+    }
     Ok(result)
 }
 
@@ -390,10 +488,287 @@ fn test_parse_test_data() {
     //  116, 114, 0, 0, 0, 2, 0, 0, 0, 3, 97, 98, 99, 0, 0, 0, 2, 100, 101, 97, 114,
     //  114, 105, 110, 116, 0, 0, 0, 3, 0, 0, 0, 123, 0, 0, 1, 200, 0, 0, 3, 21]
     assert_eq!(get_length(&data).unwrap(), 145);
-    assert_eq!(get_compression(&data).unwrap(), true);
+    assert_eq!(get_compression(&data).unwrap(), Compression::Zlib);
     let raw_data = get_raw_data(&data).unwrap();
     let (type_jump, message_type) = get_message_type(&raw_data).unwrap();
     assert_eq!(type_jump, 4);
     assert_eq!(message_type, None);
     assert_eq!(get_element_type(&raw_data[type_jump..]), "chr".to_owned());
 }
+
+#[test]
+fn test_unknown_compression() {
+    let data = [0, 0, 0, 9, 7, 0, 0, 0, 0];
+    assert_eq!(get_compression(&data).unwrap(), Compression::Unknown(7));
+    let error = WeechatMessage::from_raw_message(&data).unwrap_err();
+    assert_eq!(error.kind(), UnknownCompression);
+}
+
+#[test]
+fn test_parse_hashtable() {
+    // Hashtable of str -> int with two entries: {"one": 1, "two": 2}
+    let data = [104, 116, 98, 115, 116, 114, 105, 110, 116, 0, 0, 0, 2, 0, 0, 0, 3, 111,
+                110, 101, 0, 0, 0, 1, 0, 0, 0, 3, 116, 119, 111, 0, 0, 0, 2];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed.len(), 1);
+    assert_eq!(parsed.get(0),
+               Some(&WeechatData::Hashtable("str".to_owned(),
+                                            "int".to_owned(),
+                                            vec![(WeechatData::String("one".to_owned()),
+                                                  WeechatData::Int(1)),
+                                                 (WeechatData::String("two".to_owned()),
+                                                  WeechatData::Int(2))])));
+
+    // Empty hashtable of str -> str followed by a char, to check the length is right.
+    let data = [104, 116, 98, 115, 116, 114, 115, 116, 114, 0, 0, 0, 0, 99, 104, 114, 65];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Hashtable("str".to_owned(), "str".to_owned(), vec![]),
+                    WeechatData::Char('A')]);
+
+    // Hashtable of ptr -> htb, nested values are decoded recursively.
+    let data = [104, 116, 98, 112, 116, 114, 104, 116, 98, 0, 0, 0, 1, 4, 49, 50, 97, 98,
+                115, 116, 114, 115, 116, 114, 0, 0, 0, 1, 0, 0, 0, 1, 107, 255, 255, 255, 255];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    let inner = WeechatData::Hashtable("str".to_owned(),
+                                       "str".to_owned(),
+                                       vec![(WeechatData::String("k".to_owned()),
+                                             WeechatData::StringNull)]);
+    assert_eq!(parsed,
+               vec![WeechatData::Hashtable("ptr".to_owned(),
+                                           "htb".to_owned(),
+                                           vec![(WeechatData::Pointer("0x12ab".to_owned()),
+                                                 inner)])]);
+}
+
+#[test]
+fn test_parse_hashtable_bad_count() {
+    // Hashtable of str -> str with a count of -1.
+    let data = [104, 116, 98, 115, 116, 114, 115, 116, 114, 255, 255, 255, 255];
+    let error = parse_data(&data, data.len()).unwrap_err();
+    assert_eq!(error.kind(), MalformedBinaryParse);
+
+    // A count far past the end of the data fails instead of allocating for it.
+    let data = [104, 116, 98, 115, 116, 114, 115, 116, 114, 127, 255, 255, 255];
+    assert!(parse_data(&data, data.len()).is_err());
+}
+
+#[test]
+fn test_parse_hdata_bad_count() {
+    // hdata "buffer" with keys "number:int" and -1 rows.
+    let data = [104, 100, 97, 0, 0, 0, 6, 98, 117, 102, 102, 101, 114, 0, 0, 0, 10, 110, 117,
+                109, 98, 101, 114, 58, 105, 110, 116, 255, 255, 255, 255];
+    let error = parse_data(&data, data.len()).unwrap_err();
+    assert_eq!(error.kind(), MalformedBinaryParse);
+
+    // Far more rows than there is data for fails instead of allocating for them.
+    let data = [104, 100, 97, 0, 0, 0, 6, 98, 117, 102, 102, 101, 114, 0, 0, 0, 10, 110, 117,
+                109, 98, 101, 114, 58, 105, 110, 116, 127, 255, 255, 255];
+    assert!(parse_data(&data, data.len()).is_err());
+}
+
+#[test]
+fn test_parse_hdata_without_keys() {
+    // hdata "buffer" with no keys and one row, which is only its pointer.
+    let data = [104, 100, 97, 0, 0, 0, 6, 98, 117, 102, 102, 101, 114, 0, 0, 0, 0, 0, 0, 0, 1,
+                1, 49];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Hdata("buffer".to_owned(),
+                                       vec![WeechatData::Pointer("0x1".to_owned())],
+                                       vec![HashMap::new()])]);
+}
+
+#[test]
+fn test_parse_hdata_null_path() {
+    // hdata with a null path, null keys and no rows, what the relay sends when nothing matched.
+    let data = [104, 100, 97, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed, vec![WeechatData::Hdata("".to_owned(), vec![], vec![])]);
+}
+
+#[test]
+fn test_parse_info() {
+    // info "version" -> "1.2"
+    let data = [105, 110, 102, 0, 0, 0, 7, 118, 101, 114, 115, 105, 111, 110, 0, 0, 0, 3, 49,
+                46, 50];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Info("version".to_owned(), Some("1.2".to_owned()))]);
+
+    // info with a NULL value, followed by an int.
+    let data = [105, 110, 102, 0, 0, 0, 1, 120, 255, 255, 255, 255, 105, 110, 116, 0, 0, 0, 7];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Info("x".to_owned(), None), WeechatData::Int(7)]);
+}
+
+#[test]
+fn test_parse_infolist() {
+    // infolist "buffer" with two items, the first has a pointer and a name, the second only a
+    // number.
+    let data = [105, 110, 108, 0, 0, 0, 6, 98, 117, 102, 102, 101, 114, 0, 0, 0, 2,
+                0, 0, 0, 2,
+                0, 0, 0, 7, 112, 111, 105, 110, 116, 101, 114, 112, 116, 114, 2, 97, 98,
+                0, 0, 0, 4, 110, 97, 109, 101, 115, 116, 114, 0, 0, 0, 4, 99, 111, 114, 101,
+                0, 0, 0, 1,
+                0, 0, 0, 6, 110, 117, 109, 98, 101, 114, 105, 110, 116, 0, 0, 0, 2];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Infolist("buffer".to_owned(),
+                                          vec![vec![("pointer".to_owned(),
+                                                     WeechatData::Pointer("0xab".to_owned())),
+                                                    ("name".to_owned(),
+                                                     WeechatData::String("core".to_owned()))],
+                                               vec![("number".to_owned(),
+                                                     WeechatData::Int(2))]])]);
+}
+
+#[test]
+fn test_parse_infolist_bad_count() {
+    // infolist "x" with -1 items.
+    let data = [105, 110, 108, 0, 0, 0, 1, 120, 255, 255, 255, 255];
+    let error = parse_data(&data, data.len()).unwrap_err();
+    assert_eq!(error.kind(), MalformedBinaryParse);
+
+    // infolist "x" with one item of -2 variables.
+    let data = [105, 110, 108, 0, 0, 0, 1, 120, 0, 0, 0, 1, 255, 255, 255, 254];
+    let error = parse_data(&data, data.len()).unwrap_err();
+    assert_eq!(error.kind(), MalformedBinaryParse);
+}
+
+#[test]
+fn test_parse_array_chr() {
+    let data = [97, 114, 114, 99, 104, 114, 0, 0, 0, 2, 65, 1];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Char('A'), WeechatData::Char('\u{1}')])]);
+}
+
+#[test]
+fn test_parse_array_int() {
+    let data = [97, 114, 114, 105, 110, 116, 0, 0, 0, 2, 0, 0, 0, 123, 255, 255, 255, 255];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Int(123), WeechatData::Int(-1)])]);
+}
+
+#[test]
+fn test_parse_array_lon() {
+    let data = [97, 114, 114, 108, 111, 110, 0, 0, 0, 2, 2, 52, 50, 3, 45, 49, 48];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Long(42), WeechatData::Long(-10)])]);
+}
+
+#[test]
+fn test_parse_array_str() {
+    let data = [97, 114, 114, 115, 116, 114, 0, 0, 0, 3, 0, 0, 0, 2, 104, 105, 0, 0, 0, 0,
+                255, 255, 255, 255];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::String("hi".to_owned()),
+                                            WeechatData::String("".to_owned()),
+                                            WeechatData::StringNull])]);
+}
+
+#[test]
+fn test_parse_array_buf() {
+    let data = [97, 114, 114, 98, 117, 102, 0, 0, 0, 2, 0, 0, 0, 1, 0, 255, 255, 255, 255];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Buffer("\u{0}".to_owned()),
+                                            WeechatData::BufferNull])]);
+}
+
+#[test]
+fn test_parse_array_ptr() {
+    let data = [97, 114, 114, 112, 116, 114, 0, 0, 0, 2, 4, 49, 50, 97, 98, 1, 48];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Pointer("0x12ab".to_owned()),
+                                            WeechatData::Pointer("0x0".to_owned())])]);
+}
+
+#[test]
+fn test_parse_array_tim() {
+    let data = [97, 114, 114, 116, 105, 109, 0, 0, 0, 1, 10, 49, 52, 51, 57, 54, 53, 49, 56,
+                55, 56];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Time("1439651878".to_owned())])]);
+}
+
+#[test]
+fn test_parse_array_htb() {
+    let data = [97, 114, 114, 104, 116, 98, 0, 0, 0, 2,
+                115, 116, 114, 105, 110, 116, 0, 0, 0, 1, 0, 0, 0, 1, 97, 0, 0, 0, 1,
+                115, 116, 114, 105, 110, 116, 0, 0, 0, 0];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    let first = WeechatData::Hashtable("str".to_owned(),
+                                       "int".to_owned(),
+                                       vec![(WeechatData::String("a".to_owned()),
+                                             WeechatData::Int(1))]);
+    let second = WeechatData::Hashtable("str".to_owned(), "int".to_owned(), vec![]);
+    assert_eq!(parsed, vec![WeechatData::Array(vec![first, second])]);
+}
+
+#[test]
+fn test_parse_array_arr() {
+    let data = [97, 114, 114, 97, 114, 114, 0, 0, 0, 2,
+                105, 110, 116, 0, 0, 0, 1, 0, 0, 0, 5,
+                99, 104, 114, 0, 0, 0, 0,
+                105, 110, 116, 0, 0, 0, 9];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Array(vec![WeechatData::Int(5)]),
+                                            WeechatData::Array(vec![])]),
+                    WeechatData::Int(9)]);
+}
+
+#[test]
+fn test_parse_array_hda() {
+    // One hdata "buffer" with the key "number:int" and a single row.
+    let data = [97, 114, 114, 104, 100, 97, 0, 0, 0, 1,
+                0, 0, 0, 6, 98, 117, 102, 102, 101, 114,
+                0, 0, 0, 10, 110, 117, 109, 98, 101, 114, 58, 105, 110, 116,
+                0, 0, 0, 1, 1, 49, 0, 0, 0, 3];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    let mut row = HashMap::new();
+    row.insert("number".to_owned(), WeechatData::Int(3));
+    let hdata = WeechatData::Hdata("buffer".to_owned(),
+                                   vec![WeechatData::Pointer("0x1".to_owned())],
+                                   vec![row]);
+    assert_eq!(parsed, vec![WeechatData::Array(vec![hdata])]);
+}
+
+#[test]
+fn test_parse_array_inf() {
+    let data = [97, 114, 114, 105, 110, 102, 0, 0, 0, 2,
+                0, 0, 0, 1, 97, 0, 0, 0, 1, 49,
+                0, 0, 0, 1, 98, 255, 255, 255, 255];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    assert_eq!(parsed,
+               vec![WeechatData::Array(vec![WeechatData::Info("a".to_owned(),
+                                                              Some("1".to_owned())),
+                                            WeechatData::Info("b".to_owned(), None)])]);
+}
+
+#[test]
+fn test_parse_array_inl() {
+    // One infolist "x" with one item holding the int variable "n".
+    let data = [97, 114, 114, 105, 110, 108, 0, 0, 0, 1,
+                0, 0, 0, 1, 120, 0, 0, 0, 1,
+                0, 0, 0, 1, 0, 0, 0, 1, 110, 105, 110, 116, 0, 0, 0, 4];
+    let parsed = parse_data(&data, data.len()).unwrap();
+    let infolist = WeechatData::Infolist("x".to_owned(),
+                                         vec![vec![("n".to_owned(), WeechatData::Int(4))]]);
+    assert_eq!(parsed, vec![WeechatData::Array(vec![infolist])]);
+}
+
+#[test]
+fn test_parse_array_unknown_type() {
+    let data = [97, 114, 114, 120, 120, 120, 0, 0, 0, 1, 0];
+    let error = parse_data(&data, data.len()).unwrap_err();
+    assert_eq!(error.kind(), UnknownType);
+}
@@ -0,0 +1,79 @@
+extern crate weechat_parser;
+
+use std::io::prelude::*;
+use std::fs::File;
+use weechat_parser::{Compression, WeechatData, WeechatMessage};
+
+fn load_fixture(path: &str) -> Vec<u8> {
+    let mut f = File::open(path).unwrap();
+    let mut buffer = vec![];
+    f.read_to_end(&mut buffer).unwrap();
+    buffer
+}
+
+fn validate_test_message(message: &WeechatMessage) {
+    assert_eq!(message.id, "test");
+    assert_eq!(message.data.len(), 15);
+    assert_eq!(message.data.get(0), Some(&WeechatData::Char('A')));
+    assert_eq!(message.data.get(3), Some(&WeechatData::Long(1234567890)));
+    assert_eq!(message.data.get(5), Some(&WeechatData::String("a string".to_owned())));
+    assert_eq!(message.data.get(10), Some(&WeechatData::Pointer("0x1234abcd".to_owned())));
+    assert_eq!(message.data.get(14),
+               Some(&WeechatData::Array(vec![WeechatData::Int(123),
+                                             WeechatData::Int(456),
+                                             WeechatData::Int(789)])));
+}
+
+#[test]
+fn compressed_message() {
+    let buffer = load_fixture("./tests/fodder/test-compressed.dat");
+    assert_eq!(weechat_parser::get_compression(&buffer).unwrap(), Compression::Zlib);
+    let message = WeechatMessage::from_raw_message(&buffer).unwrap();
+    validate_test_message(&message);
+}
+
+#[test]
+fn uncompressed_message() {
+    let buffer = load_fixture("./tests/fodder/test-uncompressed.dat");
+    assert_eq!(weechat_parser::get_compression(&buffer).unwrap(), Compression::None);
+    let message = WeechatMessage::from_raw_message(&buffer).unwrap();
+    validate_test_message(&message);
+}
+
+#[test]
+fn zstd_message() {
+    let buffer = load_fixture("./tests/fodder/test-zstd.dat");
+    assert_eq!(weechat_parser::get_compression(&buffer).unwrap(), Compression::Zstd);
+    let message = WeechatMessage::from_raw_message(&buffer).unwrap();
+    validate_test_message(&message);
+}
+
+#[test]
+fn compressed_and_uncompressed_match() {
+    let compressed = WeechatMessage::from_raw_message(
+        &load_fixture("./tests/fodder/test-compressed.dat")).unwrap();
+    let uncompressed = WeechatMessage::from_raw_message(
+        &load_fixture("./tests/fodder/test-uncompressed.dat")).unwrap();
+    let zstd = WeechatMessage::from_raw_message(
+        &load_fixture("./tests/fodder/test-zstd.dat")).unwrap();
+    assert_eq!(compressed.id, uncompressed.id);
+    assert_eq!(compressed.data, uncompressed.data);
+    assert_eq!(zstd.id, uncompressed.id);
+    assert_eq!(zstd.data, uncompressed.data);
+}
+
+#[test]
+fn mixed_session() {
+    let mut buffer = load_fixture("./tests/fodder/test-uncompressed.dat");
+    buffer.extend(load_fixture("./tests/fodder/test-compressed.dat"));
+    buffer.extend(load_fixture("./tests/fodder/test-zstd.dat"));
+    buffer.extend(load_fixture("./tests/fodder/test-uncompressed.dat"));
+
+    let (tx, rx) = weechat_parser::new();
+    for item in buffer {
+        tx.send(vec![item]).unwrap();
+    }
+    for _ in 0..4 {
+        validate_test_message(&rx.recv().unwrap().unwrap());
+    }
+}
@@ -0,0 +1,125 @@
+extern crate weechat_parser;
+extern crate proptest;
+
+use std::collections::HashMap;
+use proptest::prelude::*;
+use proptest::collection::vec;
+use weechat_parser::{Compression, WeechatData, WeechatMessage};
+
+// Leaf types are picked by index so containers can hold many values of one type.
+const LEAF_TYPES: u8 = 7;
+
+fn leaf(kind: u8) -> BoxedStrategy<WeechatData> {
+    match kind {
+        0 => (0u8..=255).prop_map(|value| WeechatData::Char(value as char)).boxed(),
+        1 => any::<i32>().prop_map(WeechatData::Int).boxed(),
+        2 => any::<i64>().prop_map(WeechatData::Long).boxed(),
+        3 => prop_oneof![".*".prop_map(WeechatData::String), Just(WeechatData::StringNull)].boxed(),
+        4 => prop_oneof![".*".prop_map(WeechatData::Buffer), Just(WeechatData::BufferNull)].boxed(),
+        5 => "0x[0-9a-f]{1,16}".prop_map(WeechatData::Pointer).boxed(),
+        _ => "[0-9]{1,10}".prop_map(WeechatData::Time).boxed(),
+    }
+}
+
+fn array() -> BoxedStrategy<WeechatData> {
+    (0..LEAF_TYPES)
+        .prop_flat_map(|kind| vec(leaf(kind), 0..4))
+        .prop_map(WeechatData::Array)
+        .boxed()
+}
+
+fn typed_value(kind: u8) -> BoxedStrategy<WeechatData> {
+    if kind < LEAF_TYPES {
+        leaf(kind)
+    } else {
+        array()
+    }
+}
+
+fn type_name(kind: u8) -> &'static str {
+    ["chr", "int", "lon", "str", "buf", "ptr", "tim", "arr"][kind as usize]
+}
+
+fn hashtable() -> BoxedStrategy<WeechatData> {
+    (0..LEAF_TYPES, 0..LEAF_TYPES + 1)
+        .prop_flat_map(|(key_kind, value_kind)| {
+            vec((leaf(key_kind), typed_value(value_kind)), 0..4).prop_map(move |entries| {
+                WeechatData::Hashtable(type_name(key_kind).to_owned(),
+                                       type_name(value_kind).to_owned(),
+                                       entries)
+            })
+        })
+        .boxed()
+}
+
+fn hdata() -> BoxedStrategy<WeechatData> {
+    (1usize..4, vec(0..LEAF_TYPES + 1, 1..5), 0usize..4)
+        .prop_flat_map(|(depth, kinds, row_count)| {
+            let name = vec!["buffer"; depth].join("/");
+            let row = kinds.iter()
+                           .enumerate()
+                           .map(|(index, &kind)| (Just(format!("key{}", index)), typed_value(kind)))
+                           .collect::<Vec<_>>();
+            (Just(name),
+             vec("0x[0-9a-f]{1,16}".prop_map(WeechatData::Pointer), depth * row_count),
+             vec(row.prop_map(|pairs| pairs.into_iter().collect::<HashMap<_, _>>()),
+                 row_count))
+        })
+        .prop_map(|(name, pointers, rows)| WeechatData::Hdata(name, pointers, rows))
+        .boxed()
+}
+
+fn info() -> BoxedStrategy<WeechatData> {
+    (".*", proptest::option::of(".*"))
+        .prop_map(|(name, value)| WeechatData::Info(name, value))
+        .boxed()
+}
+
+fn infolist() -> BoxedStrategy<WeechatData> {
+    let variable = (0..LEAF_TYPES + 1).prop_flat_map(|kind| ("[a-z_]{1,10}", typed_value(kind)));
+    (".*", vec(vec(variable, 0..4), 0..4))
+        .prop_map(|(name, items)| WeechatData::Infolist(name, items))
+        .boxed()
+}
+
+fn element() -> BoxedStrategy<WeechatData> {
+    prop_oneof![(0..LEAF_TYPES).prop_flat_map(leaf),
+                array(),
+                hashtable(),
+                hdata(),
+                info(),
+                infolist()]
+        .boxed()
+}
+
+fn message() -> BoxedStrategy<WeechatMessage> {
+    (".*", vec(element(), 0..6))
+        .prop_map(|(id, data)| WeechatMessage { id: id, data: data })
+        .boxed()
+}
+
+fn compression() -> BoxedStrategy<Compression> {
+    prop_oneof![Just(Compression::None), Just(Compression::Zlib), Just(Compression::Zstd)].boxed()
+}
+
+proptest! {
+    #[test]
+    fn encode_then_parse(message in message(), compression in compression()) {
+        let encoded = message.to_bytes(compression).unwrap();
+        prop_assert_eq!(weechat_parser::get_length(&encoded).unwrap() as usize, encoded.len());
+        prop_assert_eq!(weechat_parser::get_compression(&encoded).unwrap(), compression);
+        prop_assert_eq!(WeechatMessage::from_raw_message(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn encoded_stream_through_parser(messages in vec(message(), 1..4),
+                                     compression in compression()) {
+        let (tx, rx) = weechat_parser::new();
+        for message in &messages {
+            tx.send(message.to_bytes(compression).unwrap()).unwrap();
+        }
+        for message in messages {
+            prop_assert_eq!(rx.recv().unwrap().unwrap(), message);
+        }
+    }
+}
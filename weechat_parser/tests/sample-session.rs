@@ -1,10 +1,12 @@
 extern crate weechat_parser;
 
+use std::convert::TryFrom;
 use std::io::prelude::*;
 use std::fs::File;
 use std::sync::mpsc::Receiver;
 use weechat_parser::{WeechatData, WeechatMessage};
 use weechat_parser::errors::WeechatParseError;
+use weechat_parser::events::LineAdded;
 
 macro_rules! println_stderr(
     ($($arg:tt)*) => (
@@ -48,6 +50,40 @@ fn simple_session_test() {
     }
 }
 
+#[test]
+fn typed_line_added() {
+    let mut f = File::open("./tests/fodder/simple.dat").unwrap();
+    let mut buffer = vec![];
+    f.read_to_end(&mut buffer).unwrap();
+
+    let length = weechat_parser::get_length(&buffer).unwrap() as usize;
+    let message = WeechatMessage::from_raw_message(&buffer[..length]).unwrap();
+    let line = LineAdded::try_from(&message).unwrap();
+    assert_eq!(line,
+               LineAdded {
+                   buffer: "0x7fcab15936d0".to_owned(),
+                   date: 1439651878,
+                   date_printed: 1439651878,
+                   displayed: true,
+                   highlight: false,
+                   tags: vec!["irc_privmsg".to_owned(),
+                              "notify_message".to_owned(),
+                              "prefix_nick_cyan".to_owned(),
+                              "nick_Wraithan".to_owned(),
+                              "host_~wraithan@104.236.142.65".to_owned(),
+                              "log1".to_owned()],
+                   prefix: "\u{19}F10\u{19}F13Wraithan".to_owned(),
+                   message: "Hey".to_owned(),
+               });
+
+    let length2 = weechat_parser::get_length(&buffer[length..]).unwrap() as usize;
+    let message2 = WeechatMessage::from_raw_message(&buffer[length..length + length2]).unwrap();
+    let lines = LineAdded::all_from(&message2).unwrap();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].highlight);
+    assert_eq!(lines[0].message, "test_bot: Hey");
+}
+
 #[test]
 fn blob_session() {
     let mut f = File::open("./tests/fodder/simple.dat").unwrap();